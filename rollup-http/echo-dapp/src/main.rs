@@ -44,7 +44,8 @@ pub async fn process_advance_request(
             let voucher_payload = request.payload.clone();
             let voucher = Voucher {
                 destination: request.metadata.msg_sender.clone(),
-                value: "0xdeadbeef".to_string(),
+                value: "0x00000000000000000000000000000000000000000000000000000000deadbeef"
+                    .to_string(),
                 payload: voucher_payload,
             };
 
@@ -227,7 +228,7 @@ async fn main() -> std::io::Result<()> {
             RollupRequest::Advance(advance_request) => {
                 request_response = match process_advance_request(&mut config, &advance_request).await {
                     Ok(_) => {
-                            let accept = !(config.test_config.reject == advance_request.metadata.input_index as i32);
+                            let accept = config.test_config.reject != advance_request.metadata.input_index as i32;
                             RollupResponse::Finish(accept)
                     }
                     Err(error) => {
@@ -20,7 +20,6 @@ use crate::rollup::{
 };
 use hyper::Response;
 use serde::{Deserialize, Serialize};
-use std::io::ErrorKind;
 
 #[derive(Debug, Serialize, Deserialize)]
 #[serde(tag = "request_type")]
@@ -45,8 +44,7 @@ pub async fn send_voucher(rollup_http_server_addr: &str, voucher: Voucher) {
             let id_response = serde_json::from_slice::<IndexResponse>(
                 &hyper::body::to_bytes(res)
                     .await
-                    .expect("error in voucher in response handling")
-                    .to_vec(),
+                    .expect("error in voucher in response handling"),
             );
             log::debug!("voucher generated: {:?}", &id_response);
         }
@@ -73,8 +71,7 @@ pub async fn send_notice(rollup_http_server_addr: &str, notice: Notice) {
             let id_response = serde_json::from_slice::<IndexResponse>(
                 &hyper::body::to_bytes(res)
                     .await
-                    .expect("error in notice id response handling")
-                    .to_vec(),
+                    .expect("error in notice id response handling"),
             );
             log::debug!("notice generated: {:?}", &id_response);
         }
@@ -221,12 +218,12 @@ pub async fn send_finish_request(
                     )
                     .expect("failed to decode message");
 
-                    Err(std::io::Error::new(ErrorKind::Other, finish_error))
+                    Err(std::io::Error::other(finish_error))
                 }
             }
             Err(e) => {
                 log::error!("Failed to send `{}` response to the server: {}", status, e);
-                Err(std::io::Error::new(ErrorKind::Other, e.to_string()))
+                Err(std::io::Error::other(e.to_string()))
             }
         }
     }
@@ -15,7 +15,8 @@
 //
 
 use std::env;
-use std::path::PathBuf;
+use std::path::{Path, PathBuf};
+use std::process::Command;
 
 extern crate pkg_config;
 
@@ -33,7 +34,7 @@ fn main() {
             .iter()
             .map(|path| path.to_string_lossy().into_owned())
             .collect::<Vec<String>>()
-            .get(0)
+            .first()
             .expect("Library path not found")
             .clone()
     };
@@ -71,4 +72,28 @@ fn main() {
     bindings
         .write_to_file(out_path.join("bindings.rs"))
         .expect("Couldn't write bindings!");
+
+    // build information for the startup banner, git is not available in every build environment
+    let git_commit = Command::new("git")
+        .args(["rev-parse", "--short", "HEAD"])
+        .output()
+        .ok()
+        .filter(|output| output.status.success())
+        .map(|output| String::from_utf8_lossy(&output.stdout).trim().to_string())
+        .unwrap_or_else(|| "unknown".to_string());
+    for git_path in ["../../.git/HEAD", "../../.git/refs/heads"] {
+        if Path::new(git_path).exists() {
+            println!("cargo:rerun-if-changed={}", git_path);
+        }
+    }
+    let features = env::vars()
+        .filter_map(|(key, _)| key.strip_prefix("CARGO_FEATURE_").map(str::to_lowercase))
+        .collect::<Vec<String>>()
+        .join(",");
+    println!("cargo:rustc-env=BUILD_GIT_COMMIT={}", git_commit);
+    println!(
+        "cargo:rustc-env=BUILD_PROFILE={}",
+        env::var("PROFILE").unwrap_or_default()
+    );
+    println!("cargo:rustc-env=BUILD_FEATURES={}", features);
 }
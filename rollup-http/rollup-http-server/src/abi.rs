@@ -0,0 +1,258 @@
+// Copyright Cartesi and individual authors (see AUTHORS)
+// SPDX-License-Identifier: Apache-2.0
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+// http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+//
+
+//! Minimal Ethereum ABI encoder used by the payload encoding endpoints.
+//! Supports `address`, `bool`, `uintN`, `intN`, `bytesN`, `bytes` and `string`.
+
+use serde_json::Value;
+
+use crate::hash::keccak256;
+use crate::rollup::RollupError;
+
+const WORD: usize = 32;
+
+type Word = [u8; WORD];
+
+/// Encode an event style payload: the keccak256 hash of the event signature,
+/// followed by the ABI encoding of the arguments
+pub fn encode_event(
+    signature: &str,
+    args: &[Value],
+) -> Result<Vec<u8>, Box<dyn std::error::Error>> {
+    let signature: String = signature.chars().filter(|c| !c.is_whitespace()).collect();
+    let types = parse_signature(&signature)?;
+    let mut encoded = keccak256(signature.as_bytes()).to_vec();
+    encoded.extend(encode(&types, args)?);
+    Ok(encoded)
+}
+
+/// Encode a function call: the selector, the first 4 bytes of the keccak256 hash of the
+/// function signature, followed by the ABI encoding of the arguments
+pub fn encode_call(signature: &str, args: &[Value]) -> Result<Vec<u8>, Box<dyn std::error::Error>> {
+    let mut encoded = encode_event(signature, args)?;
+    encoded.drain(4..WORD);
+    Ok(encoded)
+}
+
+/// ABI encode a list of arguments with the given types
+pub fn encode(types: &[&str], args: &[Value]) -> Result<Vec<u8>, Box<dyn std::error::Error>> {
+    if types.len() != args.len() {
+        return Err(error(&format!(
+            "expected {} arguments, got {}",
+            types.len(),
+            args.len()
+        )));
+    }
+    let mut head = Vec::with_capacity(types.len() * WORD);
+    let mut tail = Vec::new();
+    for (ty, arg) in types.iter().zip(args) {
+        match *ty {
+            "bytes" | "string" => {
+                head.extend(uint_word((types.len() * WORD + tail.len()) as u64));
+                tail.extend(encode_dynamic(ty, arg)?);
+            }
+            _ => head.extend(encode_static(ty, arg)?),
+        }
+    }
+    head.extend(tail);
+    Ok(head)
+}
+
+fn parse_signature(signature: &str) -> Result<Vec<&str>, Box<dyn std::error::Error>> {
+    let (name, params) = signature
+        .strip_suffix(')')
+        .and_then(|s| s.split_once('('))
+        .ok_or_else(|| error(&format!("invalid signature `{}`", signature)))?;
+    if name.is_empty() {
+        return Err(error(&format!("invalid signature `{}`", signature)));
+    }
+    if params.is_empty() {
+        return Ok(Vec::new());
+    }
+    Ok(params.split(',').collect())
+}
+
+fn encode_static(ty: &str, arg: &Value) -> Result<Word, Box<dyn std::error::Error>> {
+    match ty {
+        "address" => {
+            let address = decode_hex(arg)?;
+            if address.len() != 20 {
+                return Err(error(&format!("invalid address {}", arg)));
+            }
+            let mut word = [0u8; WORD];
+            word[WORD - 20..].copy_from_slice(&address);
+            Ok(word)
+        }
+        "bool" => match arg {
+            Value::Bool(value) => Ok(uint_word(*value as u64)),
+            _ => Err(error(&format!("invalid bool {}", arg))),
+        },
+        _ if ty.starts_with("uint") => {
+            let bits = type_size(ty, "uint", 8, 256)?;
+            let word = parse_uint(arg)?;
+            if (bits..256).any(|bit| bit_at(&word, bit)) {
+                return Err(error(&format!("{} does not fit in {}", arg, ty)));
+            }
+            Ok(word)
+        }
+        _ if ty.starts_with("int") => {
+            let bits = type_size(ty, "int", 8, 256)?;
+            let word = parse_int(arg)?;
+            let sign = bit_at(&word, 255);
+            if (bits - 1..256).any(|bit| bit_at(&word, bit) != sign) {
+                return Err(error(&format!("{} does not fit in {}", arg, ty)));
+            }
+            Ok(word)
+        }
+        _ if ty.starts_with("bytes") => {
+            let size = type_size(ty, "bytes", 1, 32)?;
+            let bytes = decode_hex(arg)?;
+            if bytes.len() != size {
+                return Err(error(&format!("expected {} bytes for {}", size, ty)));
+            }
+            let mut word = [0u8; WORD];
+            word[..size].copy_from_slice(&bytes);
+            Ok(word)
+        }
+        _ => Err(error(&format!("unsupported type `{}`", ty))),
+    }
+}
+
+fn encode_dynamic(ty: &str, arg: &Value) -> Result<Vec<u8>, Box<dyn std::error::Error>> {
+    let mut data = match (ty, arg) {
+        ("string", Value::String(value)) => value.as_bytes().to_vec(),
+        ("bytes", _) => decode_hex(arg)?,
+        _ => return Err(error(&format!("invalid {} {}", ty, arg))),
+    };
+    let mut encoded = uint_word(data.len() as u64).to_vec();
+    data.resize(data.len().div_ceil(WORD) * WORD, 0);
+    encoded.extend(data);
+    Ok(encoded)
+}
+
+/// Size of `uintN`, `intN` and `bytesN` types, a bare `uint` or `int` being 256 bits wide
+fn type_size(
+    ty: &str,
+    prefix: &str,
+    step: usize,
+    max: usize,
+) -> Result<usize, Box<dyn std::error::Error>> {
+    let size = &ty[prefix.len()..];
+    if size.is_empty() && prefix != "bytes" {
+        return Ok(max);
+    }
+    match size.parse::<usize>() {
+        Ok(size) if size > 0 && size <= max && size % step == 0 => Ok(size),
+        _ => Err(error(&format!("unsupported type `{}`", ty))),
+    }
+}
+
+fn parse_uint(arg: &Value) -> Result<Word, Box<dyn std::error::Error>> {
+    match arg {
+        Value::Number(number) => number
+            .as_u64()
+            .map(uint_word)
+            .ok_or_else(|| error(&format!("invalid unsigned integer {}", arg))),
+        Value::String(value) if value.starts_with("0x") => {
+            let bytes = decode_hex(arg)?;
+            if bytes.len() > WORD {
+                return Err(error(&format!("{} does not fit in 256 bits", value)));
+            }
+            let mut word = [0u8; WORD];
+            word[WORD - bytes.len()..].copy_from_slice(&bytes);
+            Ok(word)
+        }
+        Value::String(value) => parse_decimal(value),
+        _ => Err(error(&format!("invalid unsigned integer {}", arg))),
+    }
+}
+
+fn parse_int(arg: &Value) -> Result<Word, Box<dyn std::error::Error>> {
+    let (negative, magnitude) = match arg {
+        Value::Number(number) => match number.as_i64() {
+            Some(value) => (value < 0, uint_word(value.unsigned_abs())),
+            None => return Err(error(&format!("invalid integer {}", arg))),
+        },
+        Value::String(value) => match value.strip_prefix('-') {
+            Some(value) => (true, parse_decimal(value)?),
+            None => (false, parse_uint(arg)?),
+        },
+        _ => return Err(error(&format!("invalid integer {}", arg))),
+    };
+    if !negative {
+        if bit_at(&magnitude, 255) {
+            return Err(error(&format!("{} does not fit in int256", arg)));
+        }
+        return Ok(magnitude);
+    }
+    // Two's complement
+    let mut word = magnitude.map(|byte| !byte);
+    for byte in word.iter_mut().rev() {
+        let (value, overflow) = byte.overflowing_add(1);
+        *byte = value;
+        if !overflow {
+            break;
+        }
+    }
+    if !bit_at(&word, 255) && word != [0u8; WORD] {
+        return Err(error(&format!("{} does not fit in int256", arg)));
+    }
+    Ok(word)
+}
+
+fn parse_decimal(value: &str) -> Result<Word, Box<dyn std::error::Error>> {
+    if value.is_empty() {
+        return Err(error("invalid empty integer"));
+    }
+    let mut word = [0u8; WORD];
+    for digit in value.chars() {
+        let mut carry = digit
+            .to_digit(10)
+            .ok_or_else(|| error(&format!("invalid integer `{}`", value)))?;
+        for byte in word.iter_mut().rev() {
+            let product = *byte as u32 * 10 + carry;
+            *byte = product as u8;
+            carry = product >> 8;
+        }
+        if carry != 0 {
+            return Err(error(&format!("{} does not fit in 256 bits", value)));
+        }
+    }
+    Ok(word)
+}
+
+fn decode_hex(arg: &Value) -> Result<Vec<u8>, Box<dyn std::error::Error>> {
+    match arg.as_str().and_then(|value| value.strip_prefix("0x")) {
+        Some(value) => {
+            hex::decode(value).map_err(|e| error(&format!("invalid hex {}: {}", arg, e)))
+        }
+        None => Err(error(&format!("expected hex string, got {}", arg))),
+    }
+}
+
+fn uint_word(value: u64) -> Word {
+    let mut word = [0u8; WORD];
+    word[WORD - 8..].copy_from_slice(&value.to_be_bytes());
+    word
+}
+
+fn bit_at(word: &Word, bit: usize) -> bool {
+    word[WORD - 1 - bit / 8] >> (bit % 8) & 1 == 1
+}
+
+fn error(message: &str) -> Box<dyn std::error::Error> {
+    Box::new(RollupError::new(message))
+}
@@ -14,10 +14,132 @@
 // limitations under the License.
 //
 
-#[derive(Debug, Clone, PartialEq, Eq)]
+use std::collections::{HashMap, HashSet};
+use std::io::ErrorKind;
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+use std::time::Duration;
+
+use serde::{Deserialize, Deserializer};
+
+/// Prefix of the environment variables overriding the settings of a config file
+pub const ENV_PREFIX: &str = "ROLLUP_HTTP_SERVER_";
+
+/// Settings of the server. In a config file each field is a key of the same name,
+/// missing keys keeping their default value
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize)]
+#[serde(default)]
 pub struct Config {
     pub http_address: String,
     pub http_port: u16,
+    /// Unix domain socket listened on instead of `http_address` and `http_port`, removed on
+    /// shutdown. A socket left by a server that did not shut down cleanly is replaced
+    pub unix_socket: Option<PathBuf>,
+    /// Disable Nagle's algorithm on accepted connections. Small responses, such as the
+    /// reply to a finish long-poll, are sent right away at the cost of more packets
+    pub tcp_nodelay: bool,
+    /// Seconds a connection may stay idle between requests before it is closed, 5 by default.
+    /// A connection waiting on a request, such as a finish long-poll, is never idle
+    pub connection_idle_timeout_secs: Option<u64>,
+    /// `Server` header of the responses, an empty value removing it. Actix does not send one
+    pub server_header: Option<String>,
+    /// Methods allowed on a route instead of the ones it serves, an empty list disabling it
+    pub allowed_methods: HashMap<String, Vec<String>>,
+    /// Handling of trailing and repeated slashes in request paths, left untouched by default
+    pub path_normalization: PathNormalization,
+    /// Seconds after bind during which the accepted request rate ramps up, requests beyond
+    /// it being answered with a 503 and Retry-After. Unlimited afterwards. The finish, output,
+    /// gio and exception endpoints of the DApp are never throttled, for it not to fail on a 503
+    pub slow_start_secs: Option<u64>,
+    /// Requests a second accepted right after bind, the rate growing by as much every second
+    pub slow_start_rate: u32,
+    /// Milliseconds write endpoints wait for the server context and the rollup device before
+    /// answering with a 503, forever by default
+    pub lock_wait_timeout_ms: Option<u64>,
+    /// Milliseconds a finish waits for the next request before answering with a 504, forever
+    /// by default. The next finish keeps waiting for the same request
+    pub finish_timeout_ms: Option<u64>,
+    /// Maximum number of vouchers, notices and reports accepted between two finish requests,
+    /// the notices mirroring reports included
+    pub max_pending_outputs: Option<usize>,
+    /// File created once the DApp is ready to process requests, removed on shutdown
+    pub ready_file: Option<String>,
+    /// Refuse to start if the rollup device lacks the features this server needs
+    pub require_supported_device: bool,
+    /// Expose the `/debug` endpoints
+    pub debug_endpoints: bool,
+    /// Expose request counters and latencies at `/metrics`
+    pub enable_metrics: bool,
+    /// Bearer token required by the `/admin` endpoints, which are only served when it is set
+    pub admin_token: Option<String>,
+    /// Log the index of each voucher and notice written
+    pub log_output_indices: bool,
+    /// Include the indices of the outputs of the previous request in finish responses
+    pub finish_output_indices: bool,
+    /// Write a notice with the same payload after each report
+    pub mirror_reports_to_notices: bool,
+    /// Reject advances with anomalous metadata instead of only logging a warning. The finish
+    /// is answered with an error and the next one rejects the advance on the rollup device
+    pub strict_advance_metadata: bool,
+    /// Reject vouchers whose mixed-case destination fails the EIP-55 checksum. All lowercase
+    /// and all uppercase destinations carry no checksum and are accepted
+    pub enforce_checksum_addresses: bool,
+    /// Reject output payloads with uppercase hex digits, as some on-chain conventions require
+    pub hex_strict: bool,
+    /// Queue the outputs of `/voucher`, `/notice` and `/report` and answer with a ticket
+    /// polled at `/output_status`, a background task writing them to the rollup device
+    pub async_outputs: bool,
+    /// Log the outputs, gio requests and finish requests instead of sending them to the
+    /// rollup device, answering as if they succeeded. Finish requests get an empty inspect.
+    /// The device is not opened, so DApps can be exercised where there is none
+    pub dry_run: bool,
+    /// Delay before a finish request of the dry run mode is answered, in milliseconds, for a
+    /// DApp looping on finish requests not to spin
+    pub dry_run_finish_interval_ms: u64,
+    /// Maximum number of outputs waiting to be written in the asynchronous output mode
+    pub async_output_queue_size: usize,
+    /// File persisting the vouchers and notices of the current request written with an
+    /// `Idempotency-Key` header, so a restarted server does not write them again
+    pub output_sequence_file: Option<String>,
+    /// Largest advance payload handed to the DApp, larger ones are answered with an error and
+    /// rejected by the next finish
+    pub max_advance_payload_bytes: Option<usize>,
+    /// Advance payloads larger than this many bytes are left out of finish responses, the
+    /// DApp downloading them from the `payload_url` given instead
+    pub payload_download_threshold: Option<usize>,
+    /// Total size of the advance payloads kept for download, the oldest being dropped first
+    pub payload_download_max_bytes: usize,
+    /// Seconds an advance payload is kept for download
+    pub payload_download_ttl_secs: u64,
+    /// Largest json request body in bytes, of the endpoints without a limit of their own.
+    /// Binary request bodies, such as the one of `/gio_binary`, are not bound by it
+    pub max_json_body_size: usize,
+    /// Largest request body of the notice endpoints in bytes, `max_json_body_size` by default
+    pub max_notice_size: Option<usize>,
+    /// Largest request body of the report endpoints in bytes, `max_json_body_size` by default
+    pub max_report_size: Option<usize>,
+    /// GIO domains forwarded to an external http service instead of the rollup device
+    #[serde(deserialize_with = "deserialize_domains")]
+    pub gio_http_domains: HashMap<u16, String>,
+    /// Timeout of GIO requests in milliseconds, per domain
+    #[serde(deserialize_with = "deserialize_domains")]
+    pub gio_domain_timeouts: HashMap<u16, u64>,
+    /// Timeout of GIO requests in milliseconds for domains without their own, none by default
+    pub gio_default_timeout_ms: Option<u64>,
+    /// Deterministic GIO domains whose responses are cached across advances
+    pub gio_cache_domains: HashSet<u16>,
+    pub gio_cache_max_entries: usize,
+    /// Age after which a cached GIO response is requested again, never by default.
+    /// Given in seconds in config files
+    #[serde(deserialize_with = "deserialize_secs")]
+    pub gio_cache_ttl: Option<Duration>,
+    /// Uptime after which the server shuts down gracefully, to be restarted by a supervisor
+    pub max_uptime_secs: Option<u64>,
+    /// Interval of the health checks the server sends itself, shutting down when one fails.
+    /// Not available with `unix_socket`
+    pub watchdog_interval_secs: Option<u64>,
+    /// Applied to the logger at startup
+    pub log_timestamp_format: LogTimestampFormat,
 }
 
 impl Config {
@@ -25,6 +147,195 @@ impl Config {
         Self {
             http_address: String::from("127.0.0.1"),
             http_port: 5004,
+            unix_socket: None,
+            tcp_nodelay: false,
+            connection_idle_timeout_secs: None,
+            server_header: None,
+            allowed_methods: HashMap::new(),
+            path_normalization: PathNormalization::None,
+            slow_start_secs: None,
+            slow_start_rate: 10,
+            lock_wait_timeout_ms: None,
+            finish_timeout_ms: None,
+            max_pending_outputs: None,
+            ready_file: None,
+            require_supported_device: false,
+            debug_endpoints: false,
+            enable_metrics: false,
+            admin_token: None,
+            log_output_indices: false,
+            finish_output_indices: false,
+            mirror_reports_to_notices: false,
+            strict_advance_metadata: false,
+            enforce_checksum_addresses: false,
+            hex_strict: false,
+            async_outputs: false,
+            dry_run: false,
+            dry_run_finish_interval_ms: 1000,
+            async_output_queue_size: 64,
+            output_sequence_file: None,
+            max_advance_payload_bytes: None,
+            payload_download_threshold: None,
+            payload_download_max_bytes: 64 * 1024 * 1024,
+            payload_download_ttl_secs: 600,
+            max_json_body_size: 32768,
+            max_notice_size: None,
+            max_report_size: None,
+            gio_http_domains: HashMap::new(),
+            gio_domain_timeouts: HashMap::new(),
+            gio_default_timeout_ms: None,
+            gio_cache_domains: HashSet::new(),
+            gio_cache_max_entries: 1024,
+            gio_cache_ttl: None,
+            max_uptime_secs: None,
+            watchdog_interval_secs: None,
+            log_timestamp_format: LogTimestampFormat::None,
+        }
+    }
+
+    /// Read the config from a TOML file, or a YAML one with the `yaml` feature.
+    /// An environment variable named after a field, upper cased and prefixed with
+    /// [`ENV_PREFIX`], overrides the value of the file
+    pub fn from_file(path: impl AsRef<Path>) -> std::io::Result<Self> {
+        let path = path.as_ref();
+        let contents = std::fs::read_to_string(path)?;
+        let mut table = match path.extension().and_then(|extension| extension.to_str()) {
+            Some("yaml" | "yml") => parse_yaml(&contents)?,
+            _ => toml::from_str::<toml::Table>(&contents)
+                .map_err(|e| invalid_config(path, &e.to_string()))?,
+        };
+        for (name, value) in std::env::vars() {
+            if let Some(key) = name.strip_prefix(ENV_PREFIX) {
+                table.insert(key.to_lowercase(), parse_env_value(&value));
+            }
         }
+        Config::deserialize(table).map_err(|e| invalid_config(path, &e.to_string()))
+    }
+}
+
+#[cfg(feature = "yaml")]
+fn parse_yaml(contents: &str) -> std::io::Result<toml::Table> {
+    serde_yaml::from_str(contents).map_err(|e| std::io::Error::new(ErrorKind::InvalidData, e))
+}
+
+#[cfg(not(feature = "yaml"))]
+fn parse_yaml(_contents: &str) -> std::io::Result<toml::Table> {
+    Err(std::io::Error::new(
+        ErrorKind::Unsupported,
+        "yaml config files require the yaml feature",
+    ))
+}
+
+/// Environment values are TOML values, such as `5005` or `true`, falling back to strings
+fn parse_env_value(value: &str) -> toml::Value {
+    toml::from_str::<toml::Table>(&format!("value = {}", value))
+        .ok()
+        .and_then(|mut table| table.remove("value"))
+        .unwrap_or_else(|| toml::Value::String(value.to_string()))
+}
+
+fn invalid_config(path: &Path, message: &str) -> std::io::Error {
+    std::io::Error::new(
+        ErrorKind::InvalidData,
+        format!("invalid config file {}: {}", path.display(), message),
+    )
+}
+
+/// Keys of TOML tables are strings, domains are parsed from them
+fn deserialize_domains<'de, D, V>(deserializer: D) -> Result<HashMap<u16, V>, D::Error>
+where
+    D: Deserializer<'de>,
+    V: Deserialize<'de>,
+{
+    HashMap::<String, V>::deserialize(deserializer)?
+        .into_iter()
+        .map(|(domain, value)| match domain.parse::<u16>() {
+            Ok(domain) => Ok((domain, value)),
+            Err(_) => Err(serde::de::Error::custom(format!(
+                "invalid domain `{}`",
+                domain
+            ))),
+        })
+        .collect()
+}
+
+fn deserialize_secs<'de, D>(deserializer: D) -> Result<Option<Duration>, D::Error>
+where
+    D: Deserializer<'de>,
+{
+    Ok(Option::<u64>::deserialize(deserializer)?.map(Duration::from_secs))
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// Timestamp written in each log line
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(try_from = "String")]
+pub enum LogTimestampFormat {
+    None,
+    Rfc3339,
+    EpochMillis,
+}
+
+impl FromStr for LogTimestampFormat {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "none" => Ok(LogTimestampFormat::None),
+            "rfc3339" => Ok(LogTimestampFormat::Rfc3339),
+            "epoch-millis" => Ok(LogTimestampFormat::EpochMillis),
+            _ => Err(format!(
+                "unknown log timestamp format `{}`, expected none, rfc3339 or epoch-millis",
+                s
+            )),
+        }
+    }
+}
+
+impl TryFrom<String> for LogTimestampFormat {
+    type Error = String;
+
+    fn try_from(s: String) -> Result<Self, Self::Error> {
+        s.parse()
+    }
+}
+
+/// Rewriting of request paths before routing
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(try_from = "String")]
+pub enum PathNormalization {
+    None,
+    /// Merge repeated slashes and trim the trailing one, `/voucher/` routing to `/voucher`
+    Trim,
+    /// Merge repeated slashes, keeping a trailing one
+    Merge,
+}
+
+impl FromStr for PathNormalization {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "none" => Ok(PathNormalization::None),
+            "trim" => Ok(PathNormalization::Trim),
+            "merge" => Ok(PathNormalization::Merge),
+            _ => Err(format!(
+                "unknown path normalization `{}`, expected none, trim or merge",
+                s
+            )),
+        }
+    }
+}
+
+impl TryFrom<String> for PathNormalization {
+    type Error = String;
+
+    fn try_from(s: String) -> Result<Self, Self::Error> {
+        s.parse()
     }
 }
@@ -0,0 +1,92 @@
+// Copyright Cartesi and individual authors (see AUTHORS)
+// SPDX-License-Identifier: Apache-2.0
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+// http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+//
+
+//! Keccak-256 shared by the modules that hash, its implementation picked with the
+//! `tiny-keccak` (default) or `sha3` features.
+
+#[cfg(not(any(feature = "tiny-keccak", feature = "sha3")))]
+compile_error!("a keccak backend is required, enable the `tiny-keccak` or `sha3` feature");
+
+/// Keccak-256 hash of `data`, as used by Ethereum
+#[cfg(feature = "sha3")]
+pub fn keccak256(data: &[u8]) -> [u8; 32] {
+    sha3_keccak256(data)
+}
+
+/// Keccak-256 hash of `data`, as used by Ethereum
+#[cfg(not(feature = "sha3"))]
+pub fn keccak256(data: &[u8]) -> [u8; 32] {
+    tiny_keccak256(data)
+}
+
+#[cfg(feature = "sha3")]
+fn sha3_keccak256(data: &[u8]) -> [u8; 32] {
+    use sha3::{Digest, Keccak256};
+    Keccak256::digest(data).into()
+}
+
+// Left unused by `sha3` when both features are enabled, but for comparing them in tests
+#[cfg(all(feature = "tiny-keccak", any(not(feature = "sha3"), test)))]
+fn tiny_keccak256(data: &[u8]) -> [u8; 32] {
+    use tiny_keccak::{Hasher, Keccak};
+    let mut hasher = Keccak::v256();
+    let mut output = [0u8; 32];
+    hasher.update(data);
+    hasher.finalize(&mut output);
+    output
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Inputs and their hashes, checked against the published Keccak-256 vectors
+    const KNOWN_VECTORS: &[(&[u8], &str)] = &[
+        (
+            b"",
+            "c5d2460186f7233c927e7db2dcc703c0e500b653ca82273b7bfad8045d85a470",
+        ),
+        (
+            b"abc",
+            "4e03657aea45a94fc7d47ba826c8d667c0d1e6e33a64a036ec44f58fa12d6c45",
+        ),
+        (
+            b"The quick brown fox jumps over the lazy dog",
+            "4d741b6f1eb29cb2a9b9911c82f56fa8d73b04959d3d9d222895df6c0b28aa15",
+        ),
+    ];
+
+    #[test]
+    fn test_known_vectors() {
+        for (data, expected) in KNOWN_VECTORS {
+            assert_eq!(hex::encode(keccak256(data)), *expected);
+        }
+    }
+
+    /// With both features enabled, the backend left unused is compared to the other one
+    #[cfg(all(feature = "tiny-keccak", feature = "sha3"))]
+    #[test]
+    fn test_backends_agree() {
+        let long_input = vec![0xa5u8; 1000];
+        let inputs = KNOWN_VECTORS
+            .iter()
+            .map(|(data, _)| *data)
+            .chain([long_input.as_slice()]);
+        for data in inputs {
+            assert_eq!(sha3_keccak256(data), tiny_keccak256(data));
+        }
+    }
+}
@@ -14,20 +14,40 @@
 // limitations under the License.
 //
 
+use std::collections::{HashMap, VecDeque};
+use std::future::Future;
+use std::os::fd::FromRawFd;
+use std::str::FromStr;
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
 use std::sync::Arc;
+use std::time::{Duration, Instant};
 
-use actix_web::{middleware::Logger, web::Data, App, HttpResponse, HttpServer, http::header::CONTENT_TYPE};
-use actix_web_validator::Json;
-use async_mutex::Mutex;
+use actix_web::{
+    body::{self, BoxBody, EitherBody, MessageBody},
+    dev::{ServiceRequest, ServiceResponse},
+    error::{InternalError, JsonPayloadError},
+    http::header::{HeaderValue, ALLOW, AUTHORIZATION, CONTENT_TYPE, RETRY_AFTER, SERVER},
+    http::{Method, StatusCode},
+    middleware::{from_fn, Condition, Logger, Next, NormalizePath, TrailingSlash},
+    web::{self, Bytes, Data},
+    App, HttpRequest, HttpResponse, HttpResponseBuilder, HttpServer,
+};
+use actix_web_validator::{Json, JsonConfig};
+use async_mutex::{Mutex, MutexGuard};
+use futures_util::stream;
 use serde::{Deserialize, Serialize};
 use serde_json::json;
 use tokio::sync::Notify;
+use validator::Validate;
 
-use crate::config::Config;
-use crate::rollup::{self, GIORequest, RollupFd};
+use crate::abi;
+use crate::config::{Config, PathNormalization};
+use crate::hash;
+use crate::metrics::{self, Metrics};
+use crate::rollup::{self, GIORequest, GIOResponse, RollupError, RollupFd};
 use crate::rollup::{
-    AdvanceRequest, Exception, FinishRequest, InspectRequest, Notice, Report, RollupRequest,
-    Voucher,
+    AbiNotice, AdvanceMetadata, AdvanceRequest, DelegateCallVoucher, Erc20Transfer, Exception,
+    FinishRequest, InspectRequest, Notice, Report, ReportBatch, RollupRequest, Voucher,
 };
 
 #[derive(Debug, Serialize, Deserialize)]
@@ -39,89 +59,729 @@ enum RollupHttpRequest {
     Inspect { data: InspectRequest },
 }
 
+/// Reply to a finish request, the new rollup request and optionally the indices
+/// of the vouchers and notices written while processing the previous one
+#[derive(Debug, Serialize)]
+struct FinishResponse {
+    #[serde(flatten)]
+    request: RollupHttpRequest,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    previous_output_indices: Option<Vec<u64>>,
+    /// Where to download the payload of an advance too large to be inlined
+    #[serde(skip_serializing_if = "Option::is_none")]
+    payload_url: Option<String>,
+}
+
+impl RollupHttpRequest {
+    fn payload_len(&self) -> usize {
+        match self {
+            RollupHttpRequest::Advance { data } => data.payload.len(),
+            RollupHttpRequest::Inspect { data } => data.payload.len(),
+        }
+    }
+}
+
+/// Requests with a hex encoded payload larger than this are streamed to the DApp
+const FINISH_STREAMING_THRESHOLD: usize = 64 * 1024;
+/// Size of the payload chunks of a streamed finish response
+const FINISH_STREAMING_CHUNK_SIZE: usize = 64 * 1024;
+/// Number of finish durations kept for `/debug/finish_latencies`
+const FINISH_LATENCY_HISTORY: usize = 100;
+/// Number of queued output tickets whose status is kept for `/output_status`
+const OUTPUT_STATUS_HISTORY: usize = 1024;
+
+/// Kind of an output whose payload is inspected by an [`OutputValidator`]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum OutputKind {
+    Voucher,
+    Notice,
+    Report,
+}
+
+impl OutputKind {
+    fn name(&self) -> &'static str {
+        match self {
+            OutputKind::Voucher => "voucher",
+            OutputKind::Notice => "notice",
+            OutputKind::Report => "report",
+        }
+    }
+}
+
+/// Closure inspecting the decoded payload of each output before it is written, rejecting it
+/// with a message sent back to the DApp
+pub type OutputValidator = Arc<dyn Fn(OutputKind, &[u8]) -> Result<(), String> + Send + Sync>;
+
 /// Create new instance of http server
 pub fn create_server(
     config: &Config,
     rollup_fd: Arc<Mutex<RollupFd>>,
 ) -> std::io::Result<actix_server::Server> {
+    ServerBuilder::new(config, rollup_fd).build()
+}
+
+/// Builder of the http server, letting embedders enforce their own rules on the outputs
+pub struct ServerBuilder<'a> {
+    config: &'a Config,
+    rollup_fd: Arc<Mutex<RollupFd>>,
+    output_validator: Option<OutputValidator>,
+}
+
+impl<'a> ServerBuilder<'a> {
+    pub fn new(config: &'a Config, rollup_fd: Arc<Mutex<RollupFd>>) -> Self {
+        ServerBuilder {
+            config,
+            rollup_fd,
+            output_validator: None,
+        }
+    }
+
+    /// Validate the payload of each voucher, notice and report before it is written,
+    /// answering the DApp with a 400 and the message of a rejection
+    pub fn output_validator(
+        mut self,
+        validator: impl Fn(OutputKind, &[u8]) -> Result<(), String> + Send + Sync + 'static,
+    ) -> Self {
+        self.output_validator = Some(Arc::new(validator));
+        self
+    }
+
+    pub fn build(self) -> std::io::Result<actix_server::Server> {
+        let context = create_context(self.config, self.rollup_fd, self.output_validator)?;
+        serve(self.config, context)
+    }
+}
+
+fn create_context(
+    config: &Config,
+    rollup_fd: Arc<Mutex<RollupFd>>,
+    output_validator: Option<OutputValidator>,
+) -> std::io::Result<Arc<SharedContext>> {
+    let gio_http_proxies = parse_gio_http_domains(config)?;
+    let emitted_outputs = load_emitted_outputs(config)?;
+    let output_queue_ready = Arc::new(Notify::new());
+    // Context is shared between workers, so per request bookkeeping is consistent
+    let context = Arc::new(SharedContext::new(Context {
+        rollup_fd,
+        config: config.clone(),
+        pending_outputs: 0,
+        output_indices: Vec::new(),
+        last_block_timestamp: None,
+        last_block_number: None,
+        current_input_index: None,
+        inspect_reports: None,
+        ready_file: config.ready_file.clone().map(ReadyFile::new),
+        finish_latencies: VecDeque::with_capacity(FINISH_LATENCY_HISTORY),
+        gio_http_proxies,
+        gio_cache: GioCache::new(config.gio_cache_max_entries, config.gio_cache_ttl),
+        http_client: reqwest::Client::new(),
+        output_queue: OutputQueue::new(config.async_output_queue_size),
+        emitted_outputs,
+        output_sequence_file: config
+            .output_sequence_file
+            .clone()
+            .map(OutputSequenceFile::new),
+        output_sequence_version: 0,
+        output_queue_ready: output_queue_ready.clone(),
+        output_validator,
+        next_notice_sequence: 0,
+        pending_finish: None,
+        finishing: false,
+        unseen_input: false,
+        dry_run_outputs: 0,
+        payload_downloads: PayloadDownloads::new(
+            config.payload_download_max_bytes,
+            Duration::from_secs(config.payload_download_ttl_secs),
+        ),
+    }));
+    if config.async_outputs {
+        spawn_output_writer(Arc::downgrade(&context), output_queue_ready);
+    }
+    Ok(context)
+}
+
+fn serve(config: &Config, context: Arc<SharedContext>) -> std::io::Result<actix_server::Server> {
+    let data = Data::from(context);
+    let allowed_methods = Data::new(parse_allowed_methods(config)?);
+    let server_header = Data::new(parse_server_header(config)?);
+    let custom_server_header = config.server_header.is_some();
+    let debug_endpoints = config.debug_endpoints;
+    let path_normalization = config.path_normalization;
+    let metrics = Data::new(Metrics::default());
+    let enable_metrics = config.enable_metrics;
+    let admin_endpoints = config.admin_token.is_some();
+    let readiness = Data::new(Readiness::default());
+    let device_readiness = readiness.clone();
+    let active_connections = Data::new(ActiveConnections::default());
+    let connection_counter = active_connections.clone();
+    let tcp_nodelay = config.tcp_nodelay;
+    let json_limit = config.max_json_body_size;
+    let notice_limit = config.max_notice_size.unwrap_or(json_limit);
+    let report_limit = config.max_report_size.unwrap_or(json_limit);
+    let pending_request = Data::new(NextRequest::default());
+    let throttled = config.slow_start_secs.is_some();
+    let slow_start = Data::new(SlowStart::new(
+        Duration::from_secs(config.slow_start_secs.unwrap_or_default()),
+        config.slow_start_rate,
+    ));
     let server = HttpServer::new(move || {
-        let data = Data::new(Mutex::new(Context {
-            rollup_fd: rollup_fd.clone(),
-        }));
         App::new()
-            .app_data(data)
+            .app_data(data.clone())
+            .app_data(active_connections.clone())
+            .app_data(readiness.clone())
+            .app_data(metrics.clone())
+            .app_data(server_header.clone())
+            .app_data(allowed_methods.clone())
+            .app_data(slow_start.clone())
+            .app_data(pending_request.clone())
+            .app_data(validated_json_config(json_limit))
+            .app_data(json_config(json_limit))
+            .wrap(Condition::new(enable_metrics, from_fn(record_metrics)))
+            .wrap(from_fn(reject_unallowed_methods))
+            .wrap(Condition::new(
+                custom_server_header,
+                from_fn(set_server_header),
+            ))
+            .wrap(Condition::new(throttled, from_fn(throttle_slow_start)))
+            .wrap(from_fn(structured_errors))
+            .wrap(from_fn(pretty_json))
             .wrap(Logger::default())
+            .wrap(Condition::new(
+                path_normalization != PathNormalization::None,
+                NormalizePath::new(match path_normalization {
+                    PathNormalization::Merge => TrailingSlash::MergeOnly,
+                    _ => TrailingSlash::Trim,
+                }),
+            ))
             .service(voucher)
-            .service(notice)
-            .service(report)
+            .service(erc20_transfer)
+            .service(delegate_call_voucher)
+            .service(limited_resource("/notice", notice_limit).route(web::post().to(notice)))
+            .service(
+                limited_resource("/notice/abi", notice_limit).route(web::post().to(abi_notice)),
+            )
+            .service(limited_resource("/report", report_limit).route(web::post().to(report)))
+            .service(
+                limited_resource("/report/json", report_limit).route(web::post().to(json_report)),
+            )
+            .service(
+                limited_resource("/report_batch", report_limit).route(web::post().to(report_batch)),
+            )
+            .service(output_space)
+            .service(output_status)
+            .service(healthz)
+            .service(health)
+            .service(ready)
+            .service(time)
+            .service(server_config)
+            .service(current_input)
+            .service(inspect_reports)
+            .service(connections)
             .service(gio)
+            .service(gio_binary)
             .service(exception)
             .service(finish)
+            .service(next_request)
+            .service(advance_payload)
+            .configure(|cfg| {
+                if debug_endpoints {
+                    cfg.service(
+                        web::scope("/debug")
+                            .service(finish_latencies)
+                            .service(memory),
+                    );
+                }
+                if enable_metrics {
+                    cfg.service(metrics_endpoint);
+                }
+                if admin_endpoints {
+                    cfg.service(web::scope("/admin").service(clear_caches));
+                }
+            })
     })
-    .bind((config.http_address.as_str(), config.http_port))
-    .map(|t| t)?
-    .run();
+    .on_connect(move |connection, extensions| {
+        if tcp_nodelay {
+            if let Some(stream) = connection.downcast_ref::<actix_web::rt::net::TcpStream>() {
+                if let Err(e) = stream.set_nodelay(true) {
+                    log::warn!("unable to set TCP_NODELAY: {}", e);
+                }
+            }
+        }
+        // Dropped with the connection extensions when the connection closes
+        extensions.insert(ConnectionGuard::new(connection_counter.clone()));
+    });
+    // Actix only counts the time between requests, so pending long-polls are not cut
+    let server = match config.connection_idle_timeout_secs {
+        Some(secs) => server.keep_alive(Duration::from_secs(secs)),
+        None => server,
+    };
+    let server = match inherited_listener()? {
+        Some(listener) => {
+            log::info!("listening on socket inherited from systemd");
+            server.listen(listener)?
+        }
+        None => match &config.unix_socket {
+            // The health checks of the watchdog are sent over TCP
+            Some(_) if config.watchdog_interval_secs.is_some() => {
+                return Err(std::io::Error::new(
+                    std::io::ErrorKind::InvalidInput,
+                    "the watchdog cannot check a server listening on a unix socket",
+                ));
+            }
+            Some(path) => {
+                remove_stale_socket(path)?;
+                log::info!("listening on unix socket `{}`", path.display());
+                server.bind_uds(path)?
+            }
+            None => server.bind((config.http_address.as_str(), config.http_port))?,
+        },
+    };
+    let address = server.addrs().first().copied();
+    let server = server.run();
+    // The rollup device was opened by the caller, so the DApp can now ask for requests
+    device_readiness.0.store(true, Ordering::Relaxed);
+    if let (Some(interval), Some(address)) = (config.watchdog_interval_secs, address) {
+        spawn_watchdog(address, Duration::from_secs(interval), server.handle());
+    }
+    if let Some(max_uptime_secs) = config.max_uptime_secs {
+        log::info!(
+            "http service scheduled to shut down for restart in {} seconds",
+            max_uptime_secs
+        );
+        let handle = server.handle();
+        tokio::spawn(async move {
+            tokio::time::sleep(Duration::from_secs(max_uptime_secs)).await;
+            log::info!("maximum uptime reached, shutting down http service");
+            handle.stop(true).await;
+        });
+    }
     Ok(server)
 }
 
-/// Create and run new instance of http server
+/// Stop the server when it no longer answers its own health checks, for a supervisor to restart it
+fn spawn_watchdog(
+    address: std::net::SocketAddr,
+    interval: Duration,
+    handle: actix_server::ServerHandle,
+) {
+    log::info!("watchdog checking the http service every {:?}", interval);
+    let url = format!("http://{}/healthz", address);
+    // A new connection per check, so a server no longer accepting connections fails it
+    let client = reqwest::Client::builder()
+        .pool_max_idle_per_host(0)
+        .build()
+        .unwrap_or_default();
+    tokio::spawn(async move {
+        loop {
+            tokio::time::sleep(interval).await;
+            match client.get(&url).timeout(interval).send().await {
+                Ok(response) if response.status().is_success() => continue,
+                Ok(response) => log::error!("watchdog health check answered {}", response.status()),
+                Err(e) => log::error!("watchdog health check failed: {}", e),
+            }
+            log::error!("http service is unresponsive, shutting down");
+            handle.stop(false).await;
+            break;
+        }
+    });
+}
+
+/// First file descriptor passed by systemd socket activation, see sd_listen_fds(3)
+const SD_LISTEN_FDS_START: i32 = 3;
+
+/// Remove the unix socket left at `path` by a server that did not shut down cleanly, failing
+/// if a server still listens on it
+fn remove_stale_socket(path: &std::path::Path) -> std::io::Result<()> {
+    if !path.exists() {
+        return Ok(());
+    }
+    if std::os::unix::net::UnixStream::connect(path).is_ok() {
+        return Err(std::io::Error::new(
+            std::io::ErrorKind::AddrInUse,
+            format!("unix socket `{}` is in use", path.display()),
+        ));
+    }
+    log::warn!("removing stale unix socket `{}`", path.display());
+    std::fs::remove_file(path)
+}
+
+/// Socket passed by systemd when `LISTEN_PID` and `LISTEN_FDS` are set for this process.
+/// As `sd_listen_fds` does, the variables are cleared so the DApp does not take the socket
+fn inherited_listener() -> std::io::Result<Option<std::net::TcpListener>> {
+    let listen_var = |name| {
+        std::env::var(name)
+            .ok()
+            .and_then(|value| value.parse::<u32>().ok())
+    };
+    let (Some(pid), Some(fds)) = (listen_var("LISTEN_PID"), listen_var("LISTEN_FDS")) else {
+        return Ok(None);
+    };
+    if pid != std::process::id() || fds == 0 {
+        return Ok(None);
+    }
+    for name in ["LISTEN_PID", "LISTEN_FDS", "LISTEN_FDNAMES"] {
+        std::env::remove_var(name);
+    }
+    if fds > 1 {
+        log::warn!(
+            "{} sockets inherited from systemd, using the first one",
+            fds
+        );
+    }
+    check_inherited_socket(SD_LISTEN_FDS_START)?;
+    Ok(Some(unsafe {
+        std::net::TcpListener::from_raw_fd(SD_LISTEN_FDS_START)
+    }))
+}
+
+/// Check an inherited fd is a listening TCP socket, and keep it from the processes spawned
+fn check_inherited_socket(fd: i32) -> std::io::Result<()> {
+    let invalid = |problem: &str| {
+        std::io::Error::new(
+            std::io::ErrorKind::InvalidInput,
+            format!("inherited socket {} {}", fd, problem),
+        )
+    };
+    let socket_option = |option| {
+        let mut value: libc::c_int = 0;
+        let mut length = std::mem::size_of::<libc::c_int>() as libc::socklen_t;
+        let rc = unsafe {
+            libc::getsockopt(
+                fd,
+                libc::SOL_SOCKET,
+                option,
+                &mut value as *mut libc::c_int as *mut libc::c_void,
+                &mut length,
+            )
+        };
+        match rc {
+            0 => Ok(value),
+            _ => Err(invalid(&format!(
+                "is not a socket: {}",
+                std::io::Error::last_os_error()
+            ))),
+        }
+    };
+    if socket_option(libc::SO_TYPE)? != libc::SOCK_STREAM {
+        return Err(invalid("is not a stream socket"));
+    }
+    if socket_option(libc::SO_ACCEPTCONN)? == 0 {
+        return Err(invalid("is not listening"));
+    }
+    let mut address: libc::sockaddr_storage = unsafe { std::mem::zeroed() };
+    let mut length = std::mem::size_of::<libc::sockaddr_storage>() as libc::socklen_t;
+    let rc = unsafe {
+        libc::getsockname(
+            fd,
+            &mut address as *mut libc::sockaddr_storage as *mut libc::sockaddr,
+            &mut length,
+        )
+    };
+    if rc != 0 {
+        return Err(std::io::Error::last_os_error());
+    }
+    if !matches!(
+        address.ss_family as libc::c_int,
+        libc::AF_INET | libc::AF_INET6
+    ) {
+        return Err(invalid("is not a TCP socket"));
+    }
+    let flags = unsafe { libc::fcntl(fd, libc::F_GETFD) };
+    if flags == -1 || unsafe { libc::fcntl(fd, libc::F_SETFD, flags | libc::FD_CLOEXEC) } == -1 {
+        return Err(std::io::Error::last_os_error());
+    }
+    Ok(())
+}
+
+/// Create and run new instance of http server, until it is stopped or `shutdown` is notified
 pub async fn run(
     config: &Config,
     rollup_fd: Arc<Mutex<RollupFd>>,
     server_ready: Arc<Notify>,
+    shutdown: Arc<Notify>,
 ) -> std::io::Result<()> {
     log::info!("starting http dispatcher http service!");
-    let server = create_server(config, rollup_fd)?;
+    let context = create_context(config, rollup_fd, None)?;
+    let server = serve(config, context.clone())?;
+    let handle = server.handle();
     server_ready.notify_one();
-    server.await
+    tokio::spawn(async move {
+        shutdown.notified().await;
+        log::info!("shutdown requested, draining in-flight requests");
+        // A graceful stop no longer accepts connections and waits for the requests being handled
+        handle.stop(true).await;
+    });
+    server.await?;
+    // No worker is left to write the outputs queued by the last requests
+    context.lock().await.flush_output_queue().await;
+    if let Some(path) = &config.unix_socket {
+        if let Err(e) = std::fs::remove_file(path) {
+            log::error!("unable to remove unix socket `{}`: {}", path.display(), e);
+        }
+    }
+    Ok(())
 }
 
 /// Process voucher request from DApp, write voucher to rollup device
 #[actix_web::post("/voucher")]
-async fn voucher(mut voucher: Json<Voucher>, data: Data<Mutex<Context>>) -> HttpResponse {
+async fn voucher(
+    voucher: Json<Voucher>,
+    req: HttpRequest,
+    data: Data<SharedContext>,
+) -> HttpResponse {
     log::debug!("received voucher request");
-    // Check if address is valid
-    if voucher.destination.len() != (rollup::CARTESI_ROLLUP_ADDRESS_SIZE * 2 + 2) as usize
-        || (!voucher.destination.starts_with("0x"))
-    {
-        log::error!(
-            "address not valid: '{}' len: {}",
-            voucher.destination,
-            voucher.destination.len()
-        );
-        return HttpResponse::BadRequest()
-            .append_header((CONTENT_TYPE, "text/plain"))
-            .body("address not valid");
+    let output_voucher = OutputVoucher::Voucher(voucher.0);
+    if let Err(response) = output_voucher.check(&data.config) {
+        return response;
+    }
+    write_voucher(output_voucher, &req, &data).await
+}
+
+/// Process delegate call voucher request from DApp, write it to rollup device. The destination
+/// is checked as the one of `/voucher`
+#[actix_web::post("/delegate_call_voucher")]
+async fn delegate_call_voucher(
+    request: Json<DelegateCallVoucher>,
+    req: HttpRequest,
+    data: Data<SharedContext>,
+) -> HttpResponse {
+    log::debug!("received delegate call voucher request");
+    let output_voucher = OutputVoucher::DelegateCall(request.0);
+    if let Err(response) = output_voucher.check(&data.config) {
+        return response;
+    }
+    write_voucher(output_voucher, &req, &data).await
+}
+
+/// Emit a voucher calling `transfer(address,uint256)` on an ERC-20 token contract. Both
+/// addresses are checked as the destination of `/voucher`
+#[actix_web::post("/voucher/erc20_transfer")]
+async fn erc20_transfer(
+    transfer: Json<Erc20Transfer>,
+    req: HttpRequest,
+    data: Data<SharedContext>,
+) -> HttpResponse {
+    log::debug!("received erc20 transfer voucher request {:#?}", transfer);
+    for (field, address) in [("token", &transfer.token), ("to", &transfer.to)] {
+        if let Err(e) = abi::encode(&["address"], &[json!(address)]) {
+            log::error!("invalid {} address: {}", field, e);
+            return HttpResponse::BadRequest()
+                .append_header((CONTENT_TYPE, "text/plain"))
+                .body(format!("invalid {} address: {}", field, e));
+        }
+        if data.config.enforce_checksum_addresses {
+            if let Err(message) = check_address_checksum(field, address) {
+                log::error!("{}", message);
+                return HttpResponse::BadRequest()
+                    .append_header((CONTENT_TYPE, "text/plain"))
+                    .body(message);
+            }
+        }
+    }
+    let calldata = match abi::encode_call(
+        "transfer(address,uint256)",
+        &[json!(transfer.to), json!(transfer.amount)],
+    ) {
+        Ok(calldata) => calldata,
+        Err(e) => {
+            log::error!("unable to encode transfer, error details: '{}'", e);
+            return HttpResponse::BadRequest()
+                .append_header((CONTENT_TYPE, "text/plain"))
+                .body(format!("unable to encode transfer, error details: '{}'", e));
+        }
+    };
+    let transfer_voucher = Voucher {
+        destination: transfer.token.clone(),
+        value: None,
+        payload: format!("0x{}", hex::encode(calldata)),
+    };
+    write_voucher(OutputVoucher::Voucher(transfer_voucher), &req, &data).await
+}
+
+/// Write a validated voucher of either kind, or queue it in the asynchronous output mode
+async fn write_voucher(
+    mut output_voucher: OutputVoucher,
+    req: &HttpRequest,
+    data: &Data<SharedContext>,
+) -> HttpResponse {
+    let kind = output_voucher.kind();
+    let idempotency_key = idempotency_key(req);
+    let mut context = match data.lock_for_write().await {
+        Ok(context) => context,
+        Err(response) => return response,
+    };
+    if let Err(response) = context.validate_output(OutputKind::Voucher, output_voucher.payload()) {
+        return response;
+    }
+    if let Some(voucher_index) = context.emitted_output(idempotency_key.as_deref()) {
+        log::info!("{} already written with index {}", kind, voucher_index);
+        return HttpResponse::Created().json(IndexResponse {
+            index: voucher_index,
+        });
+    }
+    if context.output_limit_reached() {
+        return output_limit_response();
+    }
+    if context.config.async_outputs {
+        return queue_output(&mut context, output_voucher.into());
     }
-    let context = data.lock().await;
     // Write voucher to linux rollup device
-    return match rollup::rollup_write_voucher(&*context.rollup_fd.lock().await, &mut voucher.0) {
+    let dry_run_index = context.next_dry_run_index();
+    let result = match context
+        .write_device(kind, dry_run_index, |rollup_fd| {
+            output_voucher.write(rollup_fd)
+        })
+        .await
+    {
+        Ok(result) => result,
+        Err(response) => return response,
+    };
+    match result {
         Ok(voucher_index) => {
-            log::debug!("voucher successfully inserted {:#?}", voucher);
+            context.pending_outputs += 1;
+            context.output_indices.push(voucher_index);
+            if context.config.log_output_indices {
+                log::info!("{} written with index {}", kind, voucher_index);
+            }
+            let sequence_write = context.record_emitted_output(idempotency_key, voucher_index);
+            drop(context);
+            persist_output_sequence(sequence_write).await;
+            log::debug!("{} successfully inserted {:#?}", kind, output_voucher);
             HttpResponse::Created().json(IndexResponse {
                 index: voucher_index,
             })
         }
         Err(e) => {
-            log::error!(
-                "unable to insert voucher, error details: '{}'",
-                e.to_string()
-            );
-            HttpResponse::BadRequest()
-                .append_header((CONTENT_TYPE, "text/plain"))
-                .body(format!("unable to insert voucher, error details: '{}'", e))
+            log::error!("unable to insert {}, error details: '{}'", kind, e);
+            device_error_response(&*e)
+                .body(format!("unable to insert {}, error details: '{}'", kind, e))
         }
-    };
+    }
+}
+
+/// Voucher posted at `/voucher` or `/delegate_call_voucher`, which only differ in the value a
+/// regular voucher carries and in the device call writing them
+#[derive(Debug)]
+enum OutputVoucher {
+    Voucher(Voucher),
+    DelegateCall(DelegateCallVoucher),
+}
+
+impl OutputVoucher {
+    fn kind(&self) -> &'static str {
+        match self {
+            OutputVoucher::Voucher(_) => "voucher",
+            OutputVoucher::DelegateCall(_) => "delegate call voucher",
+        }
+    }
+
+    fn payload(&self) -> &str {
+        match self {
+            OutputVoucher::Voucher(output) => &output.payload,
+            OutputVoucher::DelegateCall(output) => &output.payload,
+        }
+    }
+
+    fn destination(&self) -> &str {
+        match self {
+            OutputVoucher::Voucher(output) => &output.destination,
+            OutputVoucher::DelegateCall(output) => &output.destination,
+        }
+    }
+
+    /// Reject a payload that is not hex and, when enforced, a destination failing its checksum
+    fn check(&self, config: &Config) -> Result<(), HttpResponse> {
+        check_payload(self.kind(), self.payload(), config.hex_strict)?;
+        if config.enforce_checksum_addresses {
+            if let Err(message) = check_address_checksum("destination", self.destination()) {
+                log::error!("{}", message);
+                return Err(HttpResponse::BadRequest()
+                    .append_header((CONTENT_TYPE, "text/plain"))
+                    .body(message));
+            }
+        }
+        Ok(())
+    }
+
+    fn write(&mut self, rollup_fd: &RollupFd) -> Result<u64, Box<dyn std::error::Error>> {
+        match self {
+            OutputVoucher::Voucher(output) => rollup::rollup_write_voucher(rollup_fd, output),
+            OutputVoucher::DelegateCall(output) => {
+                rollup::rollup_write_delegate_call_voucher(rollup_fd, output)
+            }
+        }
+    }
+}
+
+impl From<OutputVoucher> for QueuedOutput {
+    fn from(output_voucher: OutputVoucher) -> Self {
+        match output_voucher {
+            OutputVoucher::Voucher(output) => QueuedOutput::Voucher(output),
+            OutputVoucher::DelegateCall(output) => QueuedOutput::DelegateCallVoucher(output),
+        }
+    }
 }
 
-/// Process notice request from DApp, write notice to rollup device
-#[actix_web::post("/notice")]
-async fn notice(mut notice: Json<Notice>, data: Data<Mutex<Context>>) -> HttpResponse {
+/// Process notice request from DApp, write notice to rollup device. A notice giving its
+/// sequence number is rejected with a 409 when out of order
+async fn notice(
+    mut notice: Json<Notice>,
+    req: HttpRequest,
+    data: Data<SharedContext>,
+) -> HttpResponse {
     log::debug!("received notice request");
-    let context = data.lock().await;
+    let idempotency_key = idempotency_key(&req);
+    let mut context = match data.lock_for_write().await {
+        Ok(context) => context,
+        Err(response) => return response,
+    };
+    if let Err(response) = check_payload("notice", &notice.payload, context.config.hex_strict) {
+        return response;
+    }
+    if let Err(response) = context.validate_output(OutputKind::Notice, &notice.payload) {
+        return response;
+    }
+    if let Some(notice_index) = context.emitted_output(idempotency_key.as_deref()) {
+        log::info!("notice already written with index {}", notice_index);
+        return HttpResponse::Created().json(IndexResponse {
+            index: notice_index,
+        });
+    }
+    if let Err(response) = context.check_notice_sequence(notice.sequence) {
+        return response;
+    }
+    if context.output_limit_reached() {
+        return output_limit_response();
+    }
+    if context.config.async_outputs {
+        let response = queue_output(&mut context, QueuedOutput::Notice(notice.0));
+        if response.status().is_success() {
+            context.next_notice_sequence += 1;
+        }
+        return response;
+    }
     // Write notice to linux rollup device
-    return match rollup::rollup_write_notice(&*context.rollup_fd.lock().await, &mut notice.0) {
+    let dry_run_index = context.next_dry_run_index();
+    let result = match context
+        .write_device("notice", dry_run_index, |rollup_fd| {
+            rollup::rollup_write_notice(rollup_fd, &mut notice.0)
+        })
+        .await
+    {
+        Ok(result) => result,
+        Err(response) => return response,
+    };
+    match result {
         Ok(notice_index) => {
+            context.pending_outputs += 1;
+            context.output_indices.push(notice_index);
+            if context.config.log_output_indices {
+                log::info!("notice written with index {}", notice_index);
+            }
+            let sequence_write = context.record_emitted_output(idempotency_key, notice_index);
+            context.next_notice_sequence += 1;
+            drop(context);
+            persist_output_sequence(sequence_write).await;
             log::debug!("notice successfully inserted {:#?}", notice);
             HttpResponse::Created().json(IndexResponse {
                 index: notice_index,
@@ -129,150 +789,1009 @@ async fn notice(mut notice: Json<Notice>, data: Data<Mutex<Context>>) -> HttpRes
         }
         Err(e) => {
             log::error!("unable to insert notice, error details: '{}'", e);
-            HttpResponse::BadRequest()
-                .append_header((CONTENT_TYPE, "text/plain"))
+            device_error_response(&*e)
                 .body(format!("unable to insert notice, error details: '{}'", e))
         }
+    }
+}
+
+/// Process notice request from DApp with a payload to be ABI encoded, write notice to rollup device.
+/// Idempotency keys and sequence numbers are handled as those of `/notice`
+async fn abi_notice(
+    request: Json<AbiNotice>,
+    req: HttpRequest,
+    data: Data<SharedContext>,
+) -> HttpResponse {
+    log::debug!("received abi notice request {:#?}", request);
+    let payload = match abi::encode_event(&request.event_signature, &request.args) {
+        Ok(payload) => payload,
+        Err(e) => {
+            log::error!("unable to encode notice, error details: '{}'", e);
+            return HttpResponse::BadRequest()
+                .append_header((CONTENT_TYPE, "text/plain"))
+                .body(format!("unable to encode notice, error details: '{}'", e));
+        }
+    };
+    let mut encoded_notice = Notice {
+        payload: format!("0x{}", hex::encode(payload)),
+        sequence: request.sequence,
+    };
+    let idempotency_key = idempotency_key(&req);
+    let mut context = match data.lock_for_write().await {
+        Ok(context) => context,
+        Err(response) => return response,
+    };
+    if let Err(response) = context.validate_output(OutputKind::Notice, &encoded_notice.payload) {
+        return response;
+    }
+    if let Some(notice_index) = context.emitted_output(idempotency_key.as_deref()) {
+        log::info!("abi notice already written with index {}", notice_index);
+        return HttpResponse::Created().json(IndexResponse {
+            index: notice_index,
+        });
+    }
+    if let Err(response) = context.check_notice_sequence(encoded_notice.sequence) {
+        return response;
+    }
+    if context.output_limit_reached() {
+        return output_limit_response();
+    }
+    if context.config.async_outputs {
+        let response = queue_output(&mut context, QueuedOutput::Notice(encoded_notice));
+        if response.status().is_success() {
+            context.next_notice_sequence += 1;
+        }
+        return response;
+    }
+    // Write notice to linux rollup device
+    let dry_run_index = context.next_dry_run_index();
+    let result = match context
+        .write_device("notice", dry_run_index, |rollup_fd| {
+            rollup::rollup_write_notice(rollup_fd, &mut encoded_notice)
+        })
+        .await
+    {
+        Ok(result) => result,
+        Err(response) => return response,
     };
+    match result {
+        Ok(notice_index) => {
+            context.pending_outputs += 1;
+            context.output_indices.push(notice_index);
+            if context.config.log_output_indices {
+                log::info!("notice written with index {}", notice_index);
+            }
+            let sequence_write = context.record_emitted_output(idempotency_key, notice_index);
+            context.next_notice_sequence += 1;
+            drop(context);
+            persist_output_sequence(sequence_write).await;
+            log::debug!("abi notice successfully inserted {:#?}", encoded_notice);
+            HttpResponse::Created().json(IndexResponse {
+                index: notice_index,
+            })
+        }
+        Err(e) => {
+            log::error!("unable to insert notice, error details: '{}'", e);
+            device_error_response(&*e)
+                .body(format!("unable to insert notice, error details: '{}'", e))
+        }
+    }
 }
 
 /// Process report request from DApp, write report to rollup device
-#[actix_web::post("/report")]
-async fn report(report: Json<Report>, data: Data<Mutex<Context>>) -> HttpResponse {
+async fn report(report: Json<Report>, data: Data<SharedContext>) -> HttpResponse {
     log::debug!("received report request");
-    let context = data.lock().await;
+    let mut context = match data.lock_for_write().await {
+        Ok(context) => context,
+        Err(response) => return response,
+    };
+    if let Err(response) = check_payload("report", &report.payload, context.config.hex_strict) {
+        return response;
+    }
+    if let Err(response) = context.validate_output(OutputKind::Report, &report.payload) {
+        return response;
+    }
+    if context.output_limit_exceeded_by(context.report_outputs()) {
+        return output_limit_response();
+    }
+    if context.config.async_outputs {
+        return queue_output(&mut context, QueuedOutput::Report(report.0));
+    }
     // Write report to linux rollup device
-    return match rollup::rollup_write_report(&*context.rollup_fd.lock().await, &report.0) {
+    let result = match context
+        .write_device("report", (), |rollup_fd| {
+            rollup::rollup_write_report(rollup_fd, &report.0)
+        })
+        .await
+    {
+        Ok(result) => result,
+        Err(response) => return response,
+    };
+    match result {
         Ok(_) => {
+            context.pending_outputs += 1;
+            context.record_inspect_report(&report.payload);
+            if context.config.mirror_reports_to_notices {
+                if let Err(e) = context.mirror_report(&report.payload).await {
+                    log::error!("unable to mirror report as notice, error details: '{}'", e);
+                    return device_error_response(&*e).body(format!(
+                        "unable to mirror report as notice, error details: '{}'",
+                        e
+                    ));
+                }
+            }
             log::debug!("report successfully inserted {:#?}", report);
             HttpResponse::Accepted().body("")
         }
         Err(e) => {
             log::error!("unable to insert report, error details: '{}'", e);
-            HttpResponse::BadRequest()
-                .append_header((CONTENT_TYPE, "text/plain"))
+            device_error_response(&*e)
                 .body(format!("unable to insert notice, error details: '{}'", e))
         }
-    };
+    }
 }
 
-/// Process gio request and return the result
-#[actix_web::post("/gio")]
-async fn gio(request: Json<GIORequest>, data: Data<Mutex<Context>>) -> HttpResponse {
-    log::debug!("received gio request {:#?}", request);
-    let context = data.lock().await;
-    return match rollup::gio_request(&*context.rollup_fd.lock().await, &request.0) {
-        Ok(result) => {
-            log::debug!("gio successfully processed, response: {:#?}", result);
-            HttpResponse::Accepted().body(json!(result).to_string())
-        }
-        Err(e) => {
-            log::error!("unable to process gio request, error details: '{}'", e);
-            HttpResponse::BadRequest()
-                .append_header((CONTENT_TYPE, "text/plain"))
-                .body(format!(
-                    "unable to process gio request, error details: '{}'",
-                    e
-                ))
-        }
+/// Process report request from DApp with a json payload, write its UTF-8 serialization as report
+async fn json_report(
+    payload: web::Json<serde_json::Value>,
+    data: Data<SharedContext>,
+) -> HttpResponse {
+    log::debug!("received json report request");
+    let encoded = payload.0.to_string();
+    let encoded_report = Report {
+        payload: format!("0x{}", hex::encode(&encoded)),
     };
-}
-
-/// The DApp should call this method when it cannot proceed with the request processing after an exception happens.
-/// This method should be the last method ever called by the DApp backend, and it should not expect the call to return.
-/// The Rollup HTTP Server will pass the exception info to the Cartesi Server Manager.
-#[actix_web::post("/exception")]
-async fn exception(exception: Json<Exception>, data: Data<Mutex<Context>>) -> HttpResponse {
-    log::debug!("received exception request {:#?}", exception);
-
-    let context = data.lock().await;
-    // Throw an exception
-    return match rollup::rollup_throw_exception(&*context.rollup_fd.lock().await, &exception.0) {
+    let mut context = match data.lock_for_write().await {
+        Ok(context) => context,
+        Err(response) => return response,
+    };
+    if let Err(response) = context.validate_output(OutputKind::Report, &encoded_report.payload) {
+        return response;
+    }
+    if context.output_limit_exceeded_by(context.report_outputs()) {
+        return output_limit_response();
+    }
+    if context.config.async_outputs {
+        return queue_output(&mut context, QueuedOutput::Report(encoded_report));
+    }
+    // Write report to linux rollup device
+    let result = match context
+        .write_device("report", (), |rollup_fd| {
+            rollup::rollup_write_report(rollup_fd, &encoded_report)
+        })
+        .await
+    {
+        Ok(result) => result,
+        Err(response) => return response,
+    };
+    match result {
         Ok(_) => {
-            log::debug!("exception successfully thrown {:#?}", exception);
-            HttpResponse::Accepted().body("")
+            context.pending_outputs += 1;
+            context.record_inspect_report(&encoded_report.payload);
+            if context.config.mirror_reports_to_notices {
+                if let Err(e) = context.mirror_report(&encoded_report.payload).await {
+                    log::error!("unable to mirror report as notice, error details: '{}'", e);
+                    return device_error_response(&*e).body(format!(
+                        "unable to mirror report as notice, error details: '{}'",
+                        e
+                    ));
+                }
+            }
+            log::debug!("json report successfully inserted {}", encoded);
+            HttpResponse::Accepted().json(LengthResponse {
+                length: encoded.len(),
+            })
         }
         Err(e) => {
-            log::error!("unable to throw exception, error details: '{}'", e);
-            HttpResponse::BadRequest()
-                .append_header((CONTENT_TYPE, "text/plain"))
-                .body(format!("unable to throw exception, error details: '{}'", e))
+            log::error!("unable to insert report, error details: '{}'", e);
+            device_error_response(&*e)
+                .body(format!("unable to insert report, error details: '{}'", e))
         }
-    };
+    }
 }
 
-/// Process finish request from DApp, write finish to rollup device
-/// and pass RollupFinish struct to linux rollup advance/inspect requests loop thread
-#[actix_web::post("/finish")]
-async fn finish(finish: Json<FinishRequest>, data: Data<Mutex<Context>>) -> HttpResponse {
-    log::debug!("received finish request {:#?}", finish);
-    // Prepare finish status for the rollup manager
-    let accept = match finish.status.as_str() {
-        "accept" => true,
-        "reject" => false,
-        _ => {
-            return HttpResponse::BadRequest()
-                .append_header((CONTENT_TYPE, "text/plain"))
-                .body("status must be 'accept' or 'reject'");
-        }
+/// Process a batch of reports from DApp, write them to rollup device in order
+/// and answer with the number written
+async fn report_batch(batch: Json<ReportBatch>, data: Data<SharedContext>) -> HttpResponse {
+    log::debug!("received batch of {} reports", batch.payloads.len());
+    let mut context = match data.lock_for_write().await {
+        Ok(context) => context,
+        Err(response) => return response,
     };
+    for (position, payload) in batch.payloads.iter().enumerate() {
+        let output = format!("report {} of batch", position);
+        if let Err(response) = check_payload(&output, payload, context.config.hex_strict) {
+            return response;
+        }
+    }
+    for payload in &batch.payloads {
+        if let Err(response) = context.validate_output(OutputKind::Report, payload) {
+            return response;
+        }
+    }
+    // The whole batch fits within the limit, or none of it is written
+    if context.output_limit_exceeded_by(batch.payloads.len() * context.report_outputs()) {
+        return output_limit_response();
+    }
+    if context.config.async_outputs {
+        let outputs = batch
+            .payloads
+            .iter()
+            .map(|payload| {
+                QueuedOutput::Report(Report {
+                    payload: payload.clone(),
+                })
+            })
+            .collect();
+        return queue_outputs(&mut context, outputs);
+    }
+    for (position, payload) in batch.payloads.iter().enumerate() {
+        let batched_report = Report {
+            payload: payload.clone(),
+        };
+        // Write report to linux rollup device
+        let result = match context
+            .write_device("report", (), |rollup_fd| {
+                rollup::rollup_write_report(rollup_fd, &batched_report)
+            })
+            .await
+        {
+            Ok(result) => result,
+            Err(response) => return response,
+        };
+        if let Err(e) = result {
+            log::error!(
+                "unable to insert report {} of batch, error details: '{}'",
+                position,
+                e
+            );
+            return device_error_response(&*e).body(format!(
+                "unable to insert report {} of batch, {} written, error details: '{}'",
+                position, position, e
+            ));
+        }
+        context.pending_outputs += 1;
+        context.record_inspect_report(payload);
+        if context.config.mirror_reports_to_notices {
+            if let Err(e) = context.mirror_report(payload).await {
+                log::error!("unable to mirror report as notice, error details: '{}'", e);
+                return device_error_response(&*e).body(format!(
+                    "unable to mirror report as notice, error details: '{}'",
+                    e
+                ));
+            }
+        }
+    }
     log::debug!(
-        "request finished, writing to driver result `{}` ...",
-        accept
+        "batch of {} reports successfully inserted",
+        batch.payloads.len()
     );
+    HttpResponse::Accepted().json(CountResponse {
+        count: batch.payloads.len(),
+    })
+}
+
+/// Report the space available in the rollup device output buffer
+#[actix_web::get("/output_space")]
+async fn output_space(data: Data<SharedContext>) -> HttpResponse {
+    let context = match data.lock_for_write().await {
+        Ok(context) => context,
+        Err(response) => return response,
+    };
+    let rollup_fd = match context.lock_rollup_fd().await {
+        Ok(rollup_fd) => rollup_fd,
+        Err(response) => return response,
+    };
+    HttpResponse::Ok().json(OutputSpaceResponse {
+        buffer_size: rollup::rollup_output_buffer_size(&rollup_fd),
+        payload_space: rollup::rollup_output_payload_space(&rollup_fd),
+    })
+}
+
+/// Report whether an output queued in the asynchronous output mode was written
+#[actix_web::get("/output_status/{ticket}")]
+async fn output_status(ticket: web::Path<u64>, data: Data<SharedContext>) -> HttpResponse {
     let context = data.lock().await;
-    let rollup_fd = context.rollup_fd.lock().await;
-    // Write finish request, read indicator for next request
-    let new_rollup_request = match rollup::perform_rollup_finish_request(&*rollup_fd, accept).await
-    {
-        Ok(finish_request) => {
-            // Received new request, process it
+    match context.output_queue.status(*ticket) {
+        Some(status) => HttpResponse::Ok().json(status),
+        None => HttpResponse::NotFound()
+            .append_header((CONTENT_TYPE, "text/plain"))
+            .body(format!("unknown output ticket {}", ticket)),
+    }
+}
+
+/// Answer health checks without locking the context, held by finish while waiting for requests
+#[actix_web::get("/healthz")]
+async fn healthz() -> HttpResponse {
+    HttpResponse::Ok().finish()
+}
+
+/// Liveness probe, like `/healthz`
+#[actix_web::get("/health")]
+async fn health() -> HttpResponse {
+    HttpResponse::Ok().finish()
+}
+
+/// Readiness probe, answered from an atomic flag instead of the rollup device mutex
+#[actix_web::get("/ready")]
+async fn ready(readiness: Data<Readiness>) -> HttpResponse {
+    if readiness.0.load(Ordering::Relaxed) {
+        HttpResponse::Ok().finish()
+    } else {
+        HttpResponse::ServiceUnavailable()
+            .append_header((CONTENT_TYPE, "text/plain"))
+            .body("rollup device is not ready for a finish request")
+    }
+}
+
+/// Report the host wall-clock time next to the block timestamp of the last advance
+#[actix_web::get("/time")]
+async fn time(data: Data<SharedContext>) -> HttpResponse {
+    let context = data.lock().await;
+    let server_time = std::time::SystemTime::now()
+        .duration_since(std::time::UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_secs();
+    HttpResponse::Ok().json(TimeResponse {
+        server_time,
+        last_block_timestamp: context.last_block_timestamp,
+    })
+}
+
+/// Report the rollup constants and size limits clients need to know, leaving out
+/// anything secret such as the admin token
+#[actix_web::get("/config")]
+async fn server_config(data: Data<SharedContext>) -> HttpResponse {
+    let context = data.lock().await;
+    let config = &context.config;
+    HttpResponse::Ok().json(ConfigResponse {
+        version: env!("CARGO_PKG_VERSION"),
+        address_size: rollup::CARTESI_ROLLUP_ADDRESS_SIZE,
+        max_json_body_size: config.max_json_body_size,
+        max_notice_size: config.max_notice_size.unwrap_or(config.max_json_body_size),
+        max_report_size: config.max_report_size.unwrap_or(config.max_json_body_size),
+        max_advance_payload_bytes: config.max_advance_payload_bytes,
+        max_pending_outputs: config.max_pending_outputs,
+        payload_download_threshold: config.payload_download_threshold,
+    })
+}
+
+/// Report the input index of the advance being processed
+#[actix_web::get("/current_input")]
+async fn current_input(data: Data<SharedContext>) -> HttpResponse {
+    let context = data.lock().await;
+    match context.current_input_index {
+        Some(input_index) => HttpResponse::Ok().json(CurrentInputResponse { input_index }),
+        None => HttpResponse::NotFound()
+            .append_header((CONTENT_TYPE, "text/plain"))
+            .body("no advance in progress"),
+    }
+}
+
+/// Report the hex payloads of the reports written during the inspect being processed
+#[actix_web::get("/inspect/reports")]
+async fn inspect_reports(data: Data<SharedContext>) -> HttpResponse {
+    let context = data.lock().await;
+    match &context.inspect_reports {
+        Some(reports) => HttpResponse::Ok().json(reports),
+        None => HttpResponse::NotFound()
+            .append_header((CONTENT_TYPE, "text/plain"))
+            .body("no inspect in progress"),
+    }
+}
+
+/// Report the number of open http connections
+#[actix_web::get("/connections")]
+async fn connections(active_connections: Data<ActiveConnections>) -> HttpResponse {
+    HttpResponse::Ok().json(ConnectionsResponse {
+        active_connections: active_connections.0.load(Ordering::Relaxed),
+    })
+}
+
+/// Empty the in-memory caches: the gio responses, the outputs by idempotency key and the
+/// status of the queued outputs already written, answering with the number of entries cleared
+#[actix_web::post("/clear_caches")]
+async fn clear_caches(req: HttpRequest, data: Data<SharedContext>) -> HttpResponse {
+    if !data.admin_authorized(&req) {
+        return HttpResponse::Unauthorized()
+            .append_header((CONTENT_TYPE, "text/plain"))
+            .body("missing or invalid admin token");
+    }
+    let mut context = match data.lock_for_write().await {
+        Ok(context) => context,
+        Err(response) => return response,
+    };
+    let gio_responses = context.gio_cache.entries.len();
+    context.gio_cache.entries.clear();
+    let idempotency_keys = context.emitted_outputs.len();
+    let sequence_write = context.clear_emitted_outputs();
+    // Statuses of outputs still queued are kept, for their tickets to be polled
+    let statuses = &mut context.output_queue.statuses;
+    let kept_statuses = statuses.len();
+    statuses.retain(|_, status| matches!(status, OutputStatus::Pending));
+    let output_statuses = kept_statuses - statuses.len();
+    log::info!(
+        "cleared {} gio responses, {} idempotency keys and {} output statuses",
+        gio_responses,
+        idempotency_keys,
+        output_statuses
+    );
+    drop(context);
+    persist_output_sequence(sequence_write).await;
+    HttpResponse::Ok().json(ClearedCachesResponse {
+        gio_responses,
+        idempotency_keys,
+        output_statuses,
+    })
+}
+
+/// Process gio request and return the result
+#[actix_web::post("/gio")]
+async fn gio(request: Json<GIORequest>, data: Data<SharedContext>) -> HttpResponse {
+    log::debug!("received gio request {:#?}", request);
+    process_gio(request.0, data).await
+}
+
+/// Process gio request whose id is the raw request body, the domain being given in the
+/// `X-Gio-Domain` header, and return the result like `/gio`
+#[actix_web::post("/gio_binary")]
+async fn gio_binary(req: HttpRequest, id: Bytes, data: Data<SharedContext>) -> HttpResponse {
+    log::debug!("received binary gio request of {} bytes", id.len());
+    let domain = match req.headers().get(GIO_DOMAIN).map(parse_gio_domain) {
+        Some(Ok(domain)) => domain,
+        Some(Err(e)) => {
+            return HttpResponse::BadRequest()
+                .append_header((CONTENT_TYPE, "text/plain"))
+                .body(format!("invalid {} header: {}", GIO_DOMAIN, e));
+        }
+        None => {
+            return HttpResponse::BadRequest()
+                .append_header((CONTENT_TYPE, "text/plain"))
+                .body(format!("missing {} header", GIO_DOMAIN));
+        }
+    };
+    let request = GIORequest {
+        domain,
+        id: "0x".to_string() + &hex::encode(&id),
+    };
+    if let Err(e) = request.validate() {
+        return HttpResponse::BadRequest()
+            .append_header((CONTENT_TYPE, "text/plain"))
+            .body(format!("invalid gio request: {}", e));
+    }
+    process_gio(request, data).await
+}
+
+/// Header with the domain of a `/gio_binary` request
+const GIO_DOMAIN: &str = "X-Gio-Domain";
+
+/// Parse a gio domain given in decimal, or in hex with a 0x prefix
+fn parse_gio_domain(value: &HeaderValue) -> Result<u16, String> {
+    let value = value.to_str().map_err(|e| e.to_string())?.trim();
+    match value.strip_prefix("0x") {
+        Some(hex) => u16::from_str_radix(hex, 16),
+        None => value.parse(),
+    }
+    .map_err(|e| format!("`{}`: {}", value, e))
+}
+
+/// Answer a gio request from the cache, the external service of its domain or the rollup
+/// device. Only requests to the device wait for one another, the device serving a single
+/// request at a time, so requests forwarded to external services are all in flight at once
+async fn process_gio(request: GIORequest, data: Data<SharedContext>) -> HttpResponse {
+    let mut context = data.lock().await;
+    let cacheable = context.config.gio_cache_domains.contains(&request.domain);
+    if cacheable {
+        if let Some(response) = context.gio_cache.get(&request) {
+            log::debug!("gio response served from cache: {:#?}", response);
+            return HttpResponse::Accepted().body(json!(response).to_string());
+        }
+    }
+    let timeout = context
+        .config
+        .gio_domain_timeouts
+        .get(&request.domain)
+        .or(context.config.gio_default_timeout_ms.as_ref())
+        .copied();
+    // Do not hold the context while waiting for the gio response
+    let gio_request: GIOResult = match context.gio_http_proxies.get(&request.domain).cloned() {
+        Some(url) => {
+            let client = context.http_client.clone();
+            let request = request.clone();
+            Box::pin(async move { forward_gio_request(&client, url, &request).await })
+        }
+        None if context.config.dry_run => {
             log::info!(
-                "received new request of type {}",
-                match finish_request.next_request_type {
-                    0 => "ADVANCE",
-                    1 => "INSPECT",
-                    _ => "UNKNOWN",
-                }
+                "dry run, not sending gio request of domain {} to the rollup device",
+                request.domain
             );
-            match rollup::handle_rollup_requests(&*rollup_fd, finish_request).await {
-                Ok(rollup_request) => rollup_request,
-                Err(e) => {
+            Box::pin(async {
+                Ok(GIOResponse {
+                    response_code: 0,
+                    response: "0x".to_string(),
+                })
+            })
+        }
+        None => Box::pin(device_gio_request(
+            context.rollup_fd.clone(),
+            request.clone(),
+        )),
+    };
+    drop(context);
+    let result = match timeout {
+        Some(timeout) => {
+            match tokio::time::timeout(Duration::from_millis(timeout), gio_request).await {
+                Ok(result) => result,
+                Err(_) => {
                     let error_message = format!(
-                        "error performing handle_rollup_requests: `{}`",
-                        e.to_string()
+                        "gio request of domain {} timed out after {} ms",
+                        request.domain, timeout
                     );
                     log::error!("{}", &error_message);
-                    return HttpResponse::BadRequest()
+                    return HttpResponse::GatewayTimeout()
                         .append_header((CONTENT_TYPE, "text/plain"))
                         .body(error_message);
                 }
             }
         }
+        None => gio_request.await,
+    };
+    if let (true, Ok(response)) = (cacheable, &result) {
+        data.lock()
+            .await
+            .gio_cache
+            .insert(&request, response.clone());
+    }
+    match result {
+        Ok(result) => {
+            log::debug!("gio successfully processed, response: {:#?}", result);
+            HttpResponse::Accepted().body(json!(result).to_string())
+        }
         Err(e) => {
-            let error_message = format!(
-                "error performing initial finish request: `{}`",
-                e.to_string()
-            );
-            log::error!("{}", &error_message);
-            return HttpResponse::BadRequest()
-                .append_header((CONTENT_TYPE, "text/plain"))
-                .body(error_message);
+            log::error!("unable to process gio request, error details: '{}'", e);
+            device_error_response(&*e).body(format!(
+                "unable to process gio request, error details: '{}'",
+                e
+            ))
+        }
+    }
+}
+
+/// The DApp should call this method when it cannot proceed with the request processing after an exception happens.
+/// This method should be the last method ever called by the DApp backend, and it should not expect the call to return.
+/// The Rollup HTTP Server will pass the exception info to the Cartesi Server Manager.
+#[actix_web::post("/exception")]
+async fn exception(
+    exception: Json<Exception>,
+    data: Data<SharedContext>,
+    readiness: Data<Readiness>,
+) -> HttpResponse {
+    log::debug!("received exception request {:#?}", exception);
+
+    let context = match data.lock_for_write().await {
+        Ok(context) => context,
+        Err(response) => return response,
+    };
+    // Throw an exception
+    let result = match context
+        .write_device("exception", (), |rollup_fd| {
+            rollup::rollup_throw_exception(rollup_fd, &exception.0)
+        })
+        .await
+    {
+        Ok(result) => result,
+        Err(response) => return response,
+    };
+    match result {
+        Ok(_) => {
+            log::debug!("exception successfully thrown {:#?}", exception);
+            // No finish request follows an exception
+            readiness.0.store(false, Ordering::Relaxed);
+            HttpResponse::Accepted().body("")
+        }
+        Err(e) => {
+            log::error!("unable to throw exception, error details: '{}'", e);
+            device_error_response(&*e)
+                .body(format!("unable to throw exception, error details: '{}'", e))
+        }
+    }
+}
+
+/// Process finish request from DApp, write finish to rollup device
+/// and pass RollupFinish struct to linux rollup advance/inspect requests loop thread.
+/// With `?wait=false` the reply is sent right away, the next request being
+/// polled at `/next_request`
+#[actix_web::post("/finish")]
+async fn finish(
+    finish: Json<FinishRequest>,
+    query: web::Query<FinishQuery>,
+    data: Data<SharedContext>,
+    pending_request: Data<NextRequest>,
+) -> HttpResponse {
+    log::debug!("received finish request {:#?}", finish);
+    let started = Instant::now();
+    // Prepare finish status for the rollup manager
+    let accept = match finish.status.as_str() {
+        "accept" => true,
+        "reject" => false,
+        _ => {
+            return finish_response(Err(FinishError::new(
+                StatusCode::BAD_REQUEST,
+                String::from("status must be 'accept' or 'reject'"),
+            )));
+        }
+    };
+    log::debug!(
+        "request finished, writing to driver result `{}` ...",
+        accept
+    );
+    let data = data.into_inner();
+    let (device_finish, sequence_write) =
+        match start_finish_request(&mut *data.lock().await, accept).await {
+            Ok(started_finish) => started_finish,
+            Err(e) => return finish_response(Err(e)),
+        };
+    persist_output_sequence(sequence_write).await;
+    if !query.wait {
+        pending_request.set(None);
+        // The device only returns from finish with the next request, so it is waited for aside
+        actix_web::rt::spawn(async move {
+            let result = finish_rollup_request(&data, device_finish, started).await;
+            pending_request.set(Some(result));
+        });
+        return HttpResponse::Ok().finish();
+    }
+    finish_response(finish_rollup_request(&data, device_finish, started).await)
+}
+
+/// Reply with the request read by the last `?wait=false` finish, or a 204 until it is ready
+#[actix_web::get("/next_request")]
+async fn next_request(pending_request: Data<NextRequest>) -> HttpResponse {
+    match pending_request.take() {
+        Some(result) => finish_response(result),
+        None => HttpResponse::NoContent().finish(),
+    }
+}
+
+#[derive(Debug, Deserialize)]
+struct FinishQuery {
+    #[serde(default = "default_finish_wait")]
+    wait: bool,
+}
+
+fn default_finish_wait() -> bool {
+    true
+}
+
+/// Outcome of the finish request sent with `?wait=false`, once it is known
+#[derive(Default)]
+struct NextRequest(std::sync::Mutex<Option<Result<FinishResponse, FinishError>>>);
+
+impl NextRequest {
+    fn set(&self, result: Option<Result<FinishResponse, FinishError>>) {
+        *self.0.lock().unwrap_or_else(|e| e.into_inner()) = result;
+    }
+
+    fn take(&self) -> Option<Result<FinishResponse, FinishError>> {
+        self.0.lock().unwrap_or_else(|e| e.into_inner()).take()
+    }
+}
+
+/// Failed finish request. Failures to talk to the rollup device (503) and to decode the
+/// request it returned (502) have their own status, for clients to tell whether to retry
+#[derive(Debug)]
+struct FinishError {
+    status: StatusCode,
+    message: String,
+}
+
+impl FinishError {
+    fn new(status: StatusCode, message: String) -> Self {
+        log::error!("{}", &message);
+        FinishError { status, message }
+    }
+}
+
+fn finish_response(result: Result<FinishResponse, FinishError>) -> HttpResponse {
+    match result {
+        Ok(response) if response.request.payload_len() > FINISH_STREAMING_THRESHOLD => {
+            streamed_finish_response(response.request, response.previous_output_indices)
+        }
+        Ok(response) => HttpResponse::Ok()
+            .append_header((CONTENT_TYPE, "application/json"))
+            .json(response),
+        Err(e) => HttpResponse::build(e.status)
+            .append_header((CONTENT_TYPE, "text/plain"))
+            .body(e.message),
+    }
+}
+
+/// Close the current request and write the finish to the rollup device, unless a finish that
+/// timed out already did and is still waiting for the next request. The emitted outputs cleared
+/// are returned for the sequence file to be written once the context is released
+async fn start_finish_request(
+    context: &mut Context,
+    accept: bool,
+) -> Result<(DeviceFinish, Option<OutputSequenceWrite>), FinishError> {
+    if context.finishing {
+        return Err(FinishError::new(
+            StatusCode::CONFLICT,
+            String::from("a finish request is already waiting for the next request"),
+        ));
+    }
+    let mut sequence_write = None;
+    // A finish that timed out already closed the current request on the device,
+    // so its next request is waited for instead of finishing again
+    let device_finish = match context.pending_finish.take() {
+        Some(device_finish) => device_finish,
+        None => {
+            // The DApp asking for requests means it is ready to process them
+            if let Some(ready_file) = context.ready_file.as_mut() {
+                ready_file.create();
+            }
+            // Queued outputs belong to the current request, write them before closing it
+            context.flush_output_queue().await;
+            // Finish closes the current request, so its outputs are no longer pending
+            context.pending_outputs = 0;
+            context.next_notice_sequence = 0;
+            context.current_input_index = None;
+            context.inspect_reports = None;
+            sequence_write = context.clear_emitted_outputs();
+            // The DApp did not process an input answered with an error, so it cannot accept it
+            let accept = match std::mem::take(&mut context.unseen_input) {
+                true => {
+                    log::warn!(
+                        "rejecting the input answered with an error, whatever the DApp status"
+                    );
+                    false
+                }
+                false => accept,
+            };
+            if context.config.dry_run {
+                dry_run_finish_request(
+                    accept,
+                    Duration::from_millis(context.config.dry_run_finish_interval_ms),
+                )
+            } else {
+                device_finish_request(context.rollup_fd.clone(), accept)
+            }
+        }
+    };
+    context.finishing = true;
+    Ok((device_finish, sequence_write))
+}
+
+/// Wait for the next request read by a finish, then pass it to the DApp. The context is
+/// not held meanwhile, for the other endpoints to keep answering
+async fn finish_rollup_request(
+    data: &SharedContext,
+    mut device_finish: DeviceFinish,
+    started: Instant,
+) -> Result<FinishResponse, FinishError> {
+    let finish_timeout_ms = data.config.finish_timeout_ms;
+    let device_result = match finish_timeout_ms {
+        Some(timeout) => {
+            match tokio::time::timeout(Duration::from_millis(timeout), &mut device_finish).await {
+                Ok(result) => result,
+                Err(_) => {
+                    let mut context = data.lock().await;
+                    context.finishing = false;
+                    context.pending_finish = Some(device_finish);
+                    return Err(FinishError::new(
+                        StatusCode::GATEWAY_TIMEOUT,
+                        format!(
+                            "no next request from the rollup device after {} ms, finish again to keep waiting",
+                            timeout
+                        ),
+                    ));
+                }
+            }
+        }
+        None => device_finish.await,
+    };
+    let mut context = data.lock().await;
+    context.finishing = false;
+    let new_rollup_request = match device_result {
+        Ok(result) => result?,
+        Err(e) => {
+            return Err(FinishError::new(
+                StatusCode::INTERNAL_SERVER_ERROR,
+                format!("finish request task failed: `{}`", e),
+            ));
         }
     };
 
     // Respond to Dapp with the new rollup request
+    let mut payload_url = None;
     let http_rollup_request = match new_rollup_request {
-        RollupRequest::Advance(advance_request) => RollupHttpRequest::Advance {
-            data: advance_request,
-        },
-        RollupRequest::Inspect(inspect_request) => RollupHttpRequest::Inspect {
-            data: inspect_request,
-        },
+        RollupRequest::Advance(mut advance_request) => {
+            let anomalies = context.advance_metadata_anomalies(&advance_request.metadata);
+            if !anomalies.is_empty() {
+                let error_message = format!("invalid advance metadata: {}", anomalies.join("; "));
+                if context.config.strict_advance_metadata {
+                    context.unseen_input = true;
+                    return Err(FinishError::new(
+                        StatusCode::UNPROCESSABLE_ENTITY,
+                        error_message,
+                    ));
+                }
+                log::warn!("{}", &error_message);
+            }
+            // Payload is hex encoded with a 0x prefix
+            let payload_bytes = advance_request.payload.len().saturating_sub(2) / 2;
+            if let Some(max) = context.config.max_advance_payload_bytes {
+                if payload_bytes > max {
+                    context.unseen_input = true;
+                    return Err(FinishError::new(
+                        StatusCode::PAYLOAD_TOO_LARGE,
+                        format!(
+                            "advance payload of {} bytes exceeds the limit of {} bytes",
+                            payload_bytes, max
+                        ),
+                    ));
+                }
+            }
+            context.last_block_timestamp = Some(advance_request.metadata.block_timestamp);
+            context.last_block_number = Some(advance_request.metadata.block_number);
+            context.current_input_index = Some(advance_request.metadata.input_index);
+            let threshold = context.config.payload_download_threshold;
+            if threshold.is_some_and(|threshold| payload_bytes > threshold) {
+                let payload = hex::decode(&advance_request.payload[2..]).unwrap_or_default();
+                match context.payload_downloads.insert(Bytes::from(payload)) {
+                    Some(id) => {
+                        advance_request.payload = String::from("0x");
+                        payload_url = Some(format!("/advance_payload/{}", id));
+                    }
+                    None => log::warn!(
+                        "advance payload of {} bytes does not fit in the download storage, inlining it",
+                        payload_bytes
+                    ),
+                }
+            }
+            RollupHttpRequest::Advance {
+                data: advance_request,
+            }
+        }
+        RollupRequest::Inspect(inspect_request) => {
+            context.inspect_reports = Some(Vec::new());
+            RollupHttpRequest::Inspect {
+                data: inspect_request,
+            }
+        }
+    };
+    context.record_finish_latency(started.elapsed().as_millis() as u64);
+    // Only taken once the DApp gets the next request, an input answered with an error
+    // leaving them to the next finish
+    let previous_output_indices = std::mem::take(&mut context.output_indices);
+    let previous_output_indices = context
+        .config
+        .finish_output_indices
+        .then_some(previous_output_indices);
+    Ok(FinishResponse {
+        request: http_rollup_request,
+        previous_output_indices,
+        payload_url,
+    })
+}
+
+/// Serve the payload of an advance left out of its finish response
+#[actix_web::get("/advance_payload/{id}")]
+async fn advance_payload(id: web::Path<u64>, data: Data<SharedContext>) -> HttpResponse {
+    match data.lock().await.payload_downloads.get(*id) {
+        Some(payload) => HttpResponse::Ok()
+            .append_header((CONTENT_TYPE, "application/octet-stream"))
+            .body(payload),
+        None => HttpResponse::NotFound()
+            .append_header((CONTENT_TYPE, "text/plain"))
+            .body(format!("no advance payload {}, it may have expired", id)),
+    }
+}
+
+/// Expose the request counters and latencies in the Prometheus text format
+#[actix_web::get("/metrics")]
+async fn metrics_endpoint(metrics: Data<Metrics>) -> HttpResponse {
+    HttpResponse::Ok()
+        .append_header((CONTENT_TYPE, "text/plain; version=0.0.4"))
+        .body(metrics.render())
+}
+
+/// Report the durations in milliseconds of the most recent finish requests, oldest first
+#[actix_web::get("/finish_latencies")]
+async fn finish_latencies(data: Data<SharedContext>) -> HttpResponse {
+    let context = data.lock().await;
+    HttpResponse::Ok().json(&context.finish_latencies)
+}
+
+/// Report the resident and virtual memory of the process, read from `/proc/self/statm`
+#[actix_web::get("/memory")]
+async fn memory() -> HttpResponse {
+    match read_memory_usage() {
+        Ok(usage) => HttpResponse::Ok().json(usage),
+        Err(e) => {
+            log::error!("unable to read memory usage, error details: '{}'", e);
+            HttpResponse::InternalServerError()
+                .append_header((CONTENT_TYPE, "text/plain"))
+                .body(format!(
+                    "unable to read memory usage, error details: '{}'",
+                    e
+                ))
+        }
+    }
+}
+
+fn read_memory_usage() -> std::io::Result<MemoryResponse> {
+    let statm = std::fs::read_to_string("/proc/self/statm")?;
+    // Sizes are given in pages, the first being the virtual size and the second the resident set
+    let mut pages = statm.split_whitespace().map(|field| {
+        field.parse::<u64>().map_err(|e| {
+            std::io::Error::new(
+                std::io::ErrorKind::InvalidData,
+                format!("invalid statm field `{}`: {}", field, e),
+            )
+        })
+    });
+    let mut next_pages = || {
+        pages.next().unwrap_or_else(|| {
+            Err(std::io::Error::new(
+                std::io::ErrorKind::InvalidData,
+                "truncated statm",
+            ))
+        })
+    };
+    let virtual_pages = next_pages()?;
+    let resident_pages = next_pages()?;
+    let page_size = unsafe { libc::sysconf(libc::_SC_PAGESIZE) } as u64;
+    Ok(MemoryResponse {
+        rss_bytes: resident_pages * page_size,
+        virtual_bytes: virtual_pages * page_size,
+    })
+}
+
+/// Send the rollup request with chunked transfer encoding, so the payload is not
+/// copied again into a single serialized body.
+/// The json produced is the same as the serde serialization of `FinishResponse`.
+fn streamed_finish_response(
+    request: RollupHttpRequest,
+    previous_output_indices: Option<Vec<u64>>,
+) -> HttpResponse {
+    let (request_type, metadata, payload) = match request {
+        RollupHttpRequest::Advance { data } => ("advance_state", Some(data.metadata), data.payload),
+        RollupHttpRequest::Inspect { data } => ("inspect_state", None, data.payload),
     };
+    let mut head = format!("{{\"request_type\":\"{}\",\"data\":{{", request_type);
+    if let Some(metadata) = metadata {
+        head.push_str(&format!("\"metadata\":{},", json!(metadata)));
+    }
+    // Payload is hex encoded, so it needs no escaping
+    head.push_str("\"payload\":\"");
+    let payload = Bytes::from(payload);
+    let mut chunks = vec![Bytes::from(head)];
+    chunks.extend(
+        (0..payload.len())
+            .step_by(FINISH_STREAMING_CHUNK_SIZE)
+            .map(|start| {
+                payload.slice(start..payload.len().min(start + FINISH_STREAMING_CHUNK_SIZE))
+            }),
+    );
+    let mut tail = String::from("\"}");
+    if let Some(previous_output_indices) = previous_output_indices {
+        tail.push_str(&format!(
+            ",\"previous_output_indices\":{}",
+            json!(previous_output_indices)
+        ));
+    }
+    tail.push('}');
+    chunks.push(Bytes::from(tail));
+    log::debug!(
+        "streaming request with payload of {} bytes in {} chunks",
+        payload.len(),
+        chunks.len()
+    );
     HttpResponse::Ok()
         .append_header((CONTENT_TYPE, "application/json"))
-        .json(http_rollup_request)
+        .streaming(stream::iter(
+            chunks.into_iter().map(Ok::<_, actix_web::Error>),
+        ))
 }
 
 #[derive(Debug, Clone, Serialize)]
@@ -281,17 +1800,1436 @@ struct IndexResponse {
 }
 
 #[derive(Debug, Clone, Serialize)]
-struct ErrorDescription {
-    code: u16,
-    reason: String,
-    description: String,
+struct TicketResponse {
+    ticket: u64,
 }
 
-#[derive(Debug, Serialize)]
-struct Error {
-    error: ErrorDescription,
+#[derive(Debug, Clone, Serialize)]
+struct TicketsResponse {
+    tickets: Vec<u64>,
 }
 
-struct Context {
-    pub rollup_fd: Arc<Mutex<RollupFd>>,
+#[derive(Debug, Clone, Serialize)]
+struct LengthResponse {
+    length: usize,
+}
+
+#[derive(Debug, Clone, Serialize)]
+struct CountResponse {
+    count: usize,
+}
+
+#[derive(Debug, Clone, Serialize)]
+struct ConnectionsResponse {
+    active_connections: usize,
+}
+
+#[derive(Debug, Clone, Serialize)]
+struct CurrentInputResponse {
+    input_index: u64,
+}
+
+#[derive(Debug, Clone, Serialize)]
+struct ConfigResponse {
+    version: &'static str,
+    /// Size of addresses in bytes
+    address_size: u32,
+    max_json_body_size: usize,
+    max_notice_size: usize,
+    max_report_size: usize,
+    max_advance_payload_bytes: Option<usize>,
+    max_pending_outputs: Option<usize>,
+    payload_download_threshold: Option<usize>,
+}
+
+#[derive(Debug, Clone, Serialize)]
+struct TimeResponse {
+    /// Seconds since the unix epoch
+    server_time: u64,
+    last_block_timestamp: Option<u64>,
+}
+
+#[derive(Debug, Clone, Serialize)]
+struct ClearedCachesResponse {
+    gio_responses: usize,
+    idempotency_keys: usize,
+    output_statuses: usize,
+}
+
+#[derive(Debug, Clone, Serialize)]
+struct MemoryResponse {
+    rss_bytes: u64,
+    virtual_bytes: u64,
+}
+
+#[derive(Debug, Clone, Serialize)]
+struct OutputSpaceResponse {
+    buffer_size: usize,
+    payload_space: usize,
+}
+
+#[derive(Debug, Clone, Serialize)]
+struct ErrorDescription {
+    code: u16,
+    reason: String,
+    description: String,
+}
+
+#[derive(Debug, Serialize)]
+struct Error {
+    error: ErrorDescription,
+}
+
+type GIOResult =
+    std::pin::Pin<Box<dyn Future<Output = Result<GIOResponse, Box<dyn std::error::Error>>>>>;
+
+/// Perform a gio request on the rollup device, off the async workers so it can time out.
+/// A request that timed out keeps the device until it completes
+async fn device_gio_request(
+    rollup_fd: Arc<Mutex<RollupFd>>,
+    request: GIORequest,
+) -> Result<GIOResponse, Box<dyn std::error::Error>> {
+    let rollup_fd = rollup_fd.lock_arc().await;
+    tokio::task::spawn_blocking(move || {
+        rollup::gio_request(&rollup_fd, &request).map_err(|e| e.to_string())
+    })
+    .await?
+    .map_err(|e| e.into())
+}
+
+/// Finish request running on the rollup device, until it reads the next request
+type DeviceFinish = tokio::task::JoinHandle<Result<RollupRequest, FinishError>>;
+
+/// Write the finish to the rollup device and read the next request, off the async workers
+/// so the finish can time out. The device is held until the next request is read
+fn device_finish_request(rollup_fd: Arc<Mutex<RollupFd>>, accept: bool) -> DeviceFinish {
+    let runtime = tokio::runtime::Handle::current();
+    tokio::task::spawn(async move {
+        let rollup_fd = rollup_fd.lock_arc().await;
+        tokio::task::spawn_blocking(move || {
+            runtime.block_on(async {
+                // Write finish request, read indicator for next request
+                let finish_request = rollup::perform_rollup_finish_request(&rollup_fd, accept)
+                    .await
+                    .map_err(|e| {
+                        FinishError::new(
+                            StatusCode::SERVICE_UNAVAILABLE,
+                            format!("error performing initial finish request: `{}`", e),
+                        )
+                    })?;
+                // Received new request, process it
+                log::info!(
+                    "received new request of type {}",
+                    match finish_request.next_request_type {
+                        0 => "ADVANCE",
+                        1 => "INSPECT",
+                        _ => "UNKNOWN",
+                    }
+                );
+                rollup::handle_rollup_requests(&rollup_fd, finish_request)
+                    .await
+                    .map_err(|e| {
+                        FinishError::new(
+                            StatusCode::BAD_GATEWAY,
+                            format!("error performing handle_rollup_requests: `{}`", e),
+                        )
+                    })
+            })
+        })
+        .await
+        .unwrap_or_else(|e| {
+            Err(FinishError::new(
+                StatusCode::INTERNAL_SERVER_ERROR,
+                format!("finish request task failed: `{}`", e),
+            ))
+        })
+    })
+}
+
+/// Finish request of the dry run mode, answered with an inspect of empty payload after
+/// `interval`, as a device would wait for the next request
+fn dry_run_finish_request(accept: bool, interval: Duration) -> DeviceFinish {
+    log::info!(
+        "dry run, not sending finish with status {} to the rollup device",
+        if accept { "accept" } else { "reject" }
+    );
+    tokio::task::spawn(async move {
+        tokio::time::sleep(interval).await;
+        Ok(RollupRequest::Inspect(InspectRequest {
+            payload: "0x".to_string(),
+        }))
+    })
+}
+
+/// Relay a gio request to an external http service. The request is posted as json,
+/// the status and body of the reply become the gio response code and data
+async fn forward_gio_request(
+    client: &reqwest::Client,
+    url: reqwest::Url,
+    request: &GIORequest,
+) -> Result<GIOResponse, Box<dyn std::error::Error>> {
+    log::debug!(
+        "forwarding gio request of domain {} to {}",
+        request.domain,
+        url
+    );
+    let response = client
+        .post(url)
+        .header("Content-Type", "application/json")
+        .body(json!(request).to_string())
+        .send()
+        .await?;
+    let response_code = response.status().as_u16();
+    let body = response.bytes().await?;
+    Ok(GIOResponse {
+        response_code,
+        response: format!("0x{}", hex::encode(body)),
+    })
+}
+
+fn parse_gio_http_domains(config: &Config) -> std::io::Result<HashMap<u16, reqwest::Url>> {
+    config
+        .gio_http_domains
+        .iter()
+        .map(|(domain, url)| match reqwest::Url::parse(url) {
+            Ok(parsed) if parsed.scheme() == "http" => Ok((*domain, parsed)),
+            Ok(_) => Err(std::io::Error::new(
+                std::io::ErrorKind::InvalidInput,
+                format!("url `{}` of gio domain {} must use http", url, domain),
+            )),
+            Err(e) => Err(std::io::Error::new(
+                std::io::ErrorKind::InvalidInput,
+                format!("invalid url `{}` of gio domain {}: {}", url, domain, e),
+            )),
+        })
+        .collect()
+}
+
+/// Header of vouchers and notices written at most once per request, even across restarts
+const IDEMPOTENCY_KEY: &str = "Idempotency-Key";
+
+fn idempotency_key(req: &HttpRequest) -> Option<String> {
+    req.headers()
+        .get(IDEMPOTENCY_KEY)
+        .and_then(|value| value.to_str().ok())
+        .map(str::to_string)
+}
+
+/// Outputs a previous run of the server emitted for the request being processed
+fn load_emitted_outputs(config: &Config) -> std::io::Result<HashMap<String, u64>> {
+    let Some(path) = config.output_sequence_file.as_ref() else {
+        return Ok(HashMap::new());
+    };
+    match std::fs::read(path) {
+        Ok(contents) => {
+            let emitted_outputs: HashMap<String, u64> =
+                serde_json::from_slice(&contents).map_err(|e| {
+                    std::io::Error::new(
+                        std::io::ErrorKind::InvalidData,
+                        format!("invalid output sequence file {}: {}", path, e),
+                    )
+                })?;
+            if let Some(last_index) = emitted_outputs.values().max() {
+                log::info!(
+                    "resuming after {} outputs already emitted, the last with index {}",
+                    emitted_outputs.len(),
+                    last_index
+                );
+            }
+            Ok(emitted_outputs)
+        }
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(HashMap::new()),
+        Err(e) => Err(e),
+    }
+}
+
+/// File keeping the outputs emitted by idempotency key, for a restarted server not to emit
+/// them again
+struct OutputSequenceFile {
+    path: String,
+    /// Version of the last snapshot written, held while writing the next one
+    written_version: Mutex<u64>,
+}
+
+impl OutputSequenceFile {
+    fn new(path: String) -> Arc<Self> {
+        Arc::new(OutputSequenceFile {
+            path,
+            written_version: Mutex::new(0),
+        })
+    }
+}
+
+/// Snapshot of the emitted outputs taken under the context, to be written after releasing it
+struct OutputSequenceWrite {
+    file: Arc<OutputSequenceFile>,
+    version: u64,
+    contents: Vec<u8>,
+}
+
+/// Write a snapshot of the emitted outputs to the sequence file, off the executor. Snapshots
+/// are written one at a time, and one older than the last written is skipped
+async fn persist_output_sequence(sequence_write: Option<OutputSequenceWrite>) {
+    let Some(OutputSequenceWrite {
+        file,
+        version,
+        contents,
+    }) = sequence_write
+    else {
+        return;
+    };
+    let mut written_version = file.written_version.lock().await;
+    if *written_version >= version {
+        return;
+    }
+    let path = file.path.clone();
+    let result = tokio::task::spawn_blocking(move || {
+        // Written aside and renamed, so a crash never leaves a truncated file
+        let temporary_path = format!("{}.tmp", path);
+        std::fs::write(&temporary_path, contents)?;
+        std::fs::rename(&temporary_path, &path)
+    })
+    .await
+    .unwrap_or_else(|e| Err(std::io::Error::other(e)));
+    match result {
+        Ok(()) => *written_version = version,
+        Err(e) => log::error!("unable to write output sequence file {}: {}", file.path, e),
+    }
+}
+
+/// Methods served by each route
+const ROUTE_METHODS: &[(&str, Method)] = &[
+    ("/voucher", Method::POST),
+    ("/voucher/erc20_transfer", Method::POST),
+    ("/delegate_call_voucher", Method::POST),
+    ("/notice", Method::POST),
+    ("/notice/abi", Method::POST),
+    ("/report", Method::POST),
+    ("/report/json", Method::POST),
+    ("/report_batch", Method::POST),
+    ("/output_space", Method::GET),
+    ("/time", Method::GET),
+    ("/config", Method::GET),
+    ("/current_input", Method::GET),
+    ("/inspect/reports", Method::GET),
+    ("/connections", Method::GET),
+    ("/healthz", Method::GET),
+    ("/health", Method::GET),
+    ("/ready", Method::GET),
+    ("/gio", Method::POST),
+    ("/gio_binary", Method::POST),
+    ("/exception", Method::POST),
+    ("/finish", Method::POST),
+    ("/metrics", Method::GET),
+    ("/next_request", Method::GET),
+    ("/debug/finish_latencies", Method::GET),
+    ("/debug/memory", Method::GET),
+    ("/admin/clear_caches", Method::POST),
+];
+
+/// Methods allowed on each route, other methods are answered with 405
+struct AllowedMethods(HashMap<String, Vec<Method>>);
+
+fn parse_allowed_methods(config: &Config) -> std::io::Result<AllowedMethods> {
+    let mut allowed_methods: HashMap<String, Vec<Method>> = ROUTE_METHODS
+        .iter()
+        .filter(|(route, _)| config.debug_endpoints || !route.starts_with("/debug/"))
+        .filter(|(route, _)| config.admin_token.is_some() || !route.starts_with("/admin/"))
+        .map(|(route, method)| (route.to_string(), vec![method.clone()]))
+        .collect();
+    for (route, methods) in &config.allowed_methods {
+        if !allowed_methods.contains_key(route) {
+            return Err(std::io::Error::new(
+                std::io::ErrorKind::InvalidInput,
+                format!("unknown route `{}` in allowed methods", route),
+            ));
+        }
+        let methods = methods
+            .iter()
+            .map(|method| {
+                Method::from_str(&method.to_uppercase()).map_err(|e| {
+                    std::io::Error::new(
+                        std::io::ErrorKind::InvalidInput,
+                        format!("invalid method `{}` of route `{}`: {}", method, route, e),
+                    )
+                })
+            })
+            .collect::<std::io::Result<Vec<_>>>()?;
+        allowed_methods.insert(route.clone(), methods);
+    }
+    Ok(AllowedMethods(allowed_methods))
+}
+
+/// Answer requests with a method the route does not allow with 405 and the allowed methods,
+/// instead of the 404 of the default routing
+async fn reject_unallowed_methods(
+    req: ServiceRequest,
+    next: Next<impl MessageBody + 'static>,
+) -> Result<ServiceResponse<EitherBody<impl MessageBody>>, actix_web::Error> {
+    let methods = req
+        .app_data::<Data<AllowedMethods>>()
+        .and_then(|allowed_methods| allowed_methods.0.get(req.path()).cloned());
+    match methods {
+        Some(methods) if !methods.contains(req.method()) => {
+            let allow = methods
+                .iter()
+                .map(Method::as_str)
+                .collect::<Vec<_>>()
+                .join(", ");
+            let message = format!("method {} is not allowed on {}", req.method(), req.path());
+            log::warn!("{}", &message);
+            let response = HttpResponse::MethodNotAllowed()
+                .append_header((ALLOW, allow))
+                .append_header((CONTENT_TYPE, "text/plain"))
+                .body(message);
+            Ok(req.into_response(response).map_into_right_body())
+        }
+        _ => Ok(next.call(req).await?.map_into_left_body()),
+    }
+}
+
+/// Rate limiter relaxing over time, so a burst of requests right after bind does not
+/// hit a DApp still warming up
+struct SlowStart {
+    started: Instant,
+    window: Duration,
+    initial_rate: f64,
+    /// Requests that may still be accepted, refilled at the current rate, and when it was last
+    tokens: std::sync::Mutex<(f64, Instant)>,
+}
+
+impl SlowStart {
+    fn new(window: Duration, initial_rate: u32) -> Self {
+        let initial_rate = initial_rate.max(1) as f64;
+        let started = Instant::now();
+        SlowStart {
+            started,
+            window,
+            initial_rate,
+            tokens: std::sync::Mutex::new((initial_rate, started)),
+        }
+    }
+
+    /// Take a token if there is one, the limit being lifted once the window elapsed
+    fn accept(&self) -> bool {
+        let now = Instant::now();
+        let elapsed = now.duration_since(self.started);
+        if elapsed >= self.window {
+            return true;
+        }
+        // The rate grows by the initial one every second
+        let rate = self.initial_rate * (1.0 + elapsed.as_secs_f64());
+        let mut tokens = self.tokens.lock().unwrap_or_else(|e| e.into_inner());
+        let (available, refilled) = *tokens;
+        let available = (available + rate * now.duration_since(refilled).as_secs_f64()).min(rate);
+        if available < 1.0 {
+            *tokens = (available, now);
+            return false;
+        }
+        *tokens = (available - 1.0, now);
+        true
+    }
+}
+
+/// Answer requests beyond the slow start rate with a 503, health and readiness checks being
+/// exempt. So are the endpoints the DApp drives its requests with, as its client does not
+/// retry them
+async fn throttle_slow_start(
+    req: ServiceRequest,
+    next: Next<impl MessageBody + 'static>,
+) -> Result<ServiceResponse<EitherBody<impl MessageBody>>, actix_web::Error> {
+    let accepted = matches!(
+        req.path(),
+        "/healthz" | "/health" | "/ready" | "/finish" | "/next_request"
+    ) || metrics::Handler::from_path(req.path()).is_some()
+        || req
+            .app_data::<Data<SlowStart>>()
+            .is_none_or(|slow_start| slow_start.accept());
+    if accepted {
+        return Ok(next.call(req).await?.map_into_left_body());
+    }
+    log::warn!("slow start throttling request to {}", req.path());
+    let response = HttpResponse::ServiceUnavailable()
+        .append_header((RETRY_AFTER, "1"))
+        .append_header((CONTENT_TYPE, "text/plain"))
+        .body("server is starting, request throttled");
+    Ok(req.into_response(response).map_into_right_body())
+}
+
+/// Count the successful and failed requests of the output, gio and exception endpoints
+/// and time their handling
+async fn record_metrics(
+    req: ServiceRequest,
+    next: Next<impl MessageBody + 'static>,
+) -> Result<ServiceResponse<impl MessageBody>, actix_web::Error> {
+    let handler = metrics::Handler::from_path(req.path());
+    let metrics = req.app_data::<Data<Metrics>>().cloned();
+    let started = Instant::now();
+    let response = next.call(req).await?;
+    if let (Some(handler), Some(metrics)) = (handler, metrics) {
+        let status = response.status();
+        let failed = status.is_client_error() || status.is_server_error();
+        metrics.record(handler, started.elapsed(), failed);
+    }
+    Ok(response)
+}
+
+/// Value of the `Server` header of the responses, removed when `None`
+struct ServerHeader(Option<HeaderValue>);
+
+fn parse_server_header(config: &Config) -> std::io::Result<ServerHeader> {
+    match config.server_header.as_deref() {
+        None | Some("") => Ok(ServerHeader(None)),
+        Some(value) => match HeaderValue::from_str(value) {
+            Ok(value) => Ok(ServerHeader(Some(value))),
+            Err(e) => Err(std::io::Error::new(
+                std::io::ErrorKind::InvalidInput,
+                format!("invalid server header `{}`: {}", value, e),
+            )),
+        },
+    }
+}
+
+/// Replace the `Server` header of the responses with the configured one, or remove it
+async fn set_server_header(
+    req: ServiceRequest,
+    next: Next<impl MessageBody + 'static>,
+) -> Result<ServiceResponse<impl MessageBody>, actix_web::Error> {
+    let server_header = req
+        .app_data::<Data<ServerHeader>>()
+        .and_then(|server_header| server_header.0.clone());
+    let mut response = next.call(req).await?;
+    let headers = response.headers_mut();
+    match server_header {
+        Some(value) => {
+            headers.insert(SERVER, value);
+        }
+        None => {
+            headers.remove(SERVER);
+        }
+    }
+    Ok(response)
+}
+
+#[derive(Debug, Deserialize)]
+struct PrettyQuery {
+    #[serde(default)]
+    pretty: bool,
+}
+
+/// Indent the json responses of requests with `?pretty=true`, for debugging with curl
+async fn pretty_json(
+    req: ServiceRequest,
+    next: Next<impl MessageBody + 'static>,
+) -> Result<ServiceResponse<BoxBody>, actix_web::Error> {
+    let pretty = web::Query::<PrettyQuery>::from_query(req.query_string())
+        .map(|query| query.pretty)
+        .unwrap_or(false);
+    let response = next.call(req).await?;
+    let is_json = response
+        .headers()
+        .get(CONTENT_TYPE)
+        .is_some_and(|value| value.as_bytes().starts_with(b"application/json"));
+    if !pretty || !is_json {
+        return Ok(response.map_into_boxed_body());
+    }
+    let (request, response) = response.into_parts();
+    let (response, response_body) = response.into_parts();
+    let response_body = body::to_bytes(response_body)
+        .await
+        .map_err(|e| actix_web::error::ErrorInternalServerError(e.into().to_string()))?;
+    let pretty_body = match serde_json::from_slice::<serde_json::Value>(&response_body) {
+        Ok(value) => serde_json::to_vec_pretty(&value).unwrap_or(response_body.to_vec()),
+        Err(_) => response_body.to_vec(),
+    };
+    Ok(ServiceResponse::new(
+        request,
+        response.set_body(BoxBody::new(pretty_body)),
+    ))
+}
+
+/// Answer every failed request with a json `Error`, so clients parse failures uniformly.
+/// Handlers describe a failure with a text body, taken as the description, the reason
+/// naming the endpoint
+async fn structured_errors(
+    req: ServiceRequest,
+    next: Next<impl MessageBody + 'static>,
+) -> Result<ServiceResponse<BoxBody>, actix_web::Error> {
+    let endpoint = req.path().to_string();
+    let response = next.call(req).await?;
+    let status = response.status();
+    if !status.is_client_error() && !status.is_server_error() {
+        return Ok(response.map_into_boxed_body());
+    }
+    let (request, response) = response.into_parts();
+    let (mut response, response_body) = response.into_parts();
+    let response_body = body::to_bytes(response_body)
+        .await
+        .map_err(|e| actix_web::error::ErrorInternalServerError(e.into().to_string()))?;
+    let canonical_reason = status.canonical_reason().unwrap_or_default();
+    let description = match String::from_utf8_lossy(&response_body) {
+        message if message.is_empty() => canonical_reason.to_string(),
+        message => message.into_owned(),
+    };
+    let error = Error {
+        error: ErrorDescription {
+            code: status.as_u16(),
+            reason: format!("{}: {}", endpoint, canonical_reason),
+            description,
+        },
+    };
+    response
+        .headers_mut()
+        .insert(CONTENT_TYPE, HeaderValue::from_static("application/json"));
+    Ok(ServiceResponse::new(
+        request,
+        response.set_body(BoxBody::new(json!(error).to_string())),
+    ))
+}
+
+/// Config of the validated json extractor, answering oversized bodies with a 413
+fn validated_json_config(limit: usize) -> JsonConfig {
+    JsonConfig::default()
+        .limit(limit)
+        .error_handler(json_error_handler)
+}
+
+/// Config of the plain json extractor, answering oversized bodies with a 413
+fn json_config(limit: usize) -> web::JsonConfig {
+    web::JsonConfig::default()
+        .limit(limit)
+        .error_handler(json_payload_error_handler)
+}
+
+/// Route whose json request bodies are limited to `limit` bytes
+fn limited_resource(path: &str, limit: usize) -> actix_web::Resource {
+    web::resource(path)
+        .app_data(validated_json_config(limit))
+        .app_data(json_config(limit))
+}
+
+/// Tell the DApp by how much an oversized request body exceeds the limit,
+/// or which fields failed validation
+fn json_error_handler(err: actix_web_validator::Error, req: &HttpRequest) -> actix_web::Error {
+    match err {
+        actix_web_validator::Error::JsonPayloadError(err) => json_payload_error_handler(err, req),
+        actix_web_validator::Error::Validate(errors) => {
+            let response = validation_error_response(&errors);
+            InternalError::from_response(errors, response).into()
+        }
+        err => err.into(),
+    }
+}
+
+/// Describe each field that failed validation, as `field: code` when there is no message
+fn validation_error_response(errors: &validator::ValidationErrors) -> HttpResponse {
+    let mut fields: Vec<String> = errors
+        .field_errors()
+        .iter()
+        .map(|(field, errors)| {
+            let messages: Vec<&str> = errors
+                .iter()
+                .map(|error| error.message.as_deref().unwrap_or(&error.code))
+                .collect();
+            format!("{}: {}", field, messages.join(", "))
+        })
+        .collect();
+    fields.sort();
+    let description = fields.join("; ");
+    log::error!("request validation failed: {}", description);
+    HttpResponse::BadRequest()
+        .append_header((CONTENT_TYPE, "text/plain"))
+        .body(description)
+}
+
+/// Same as `json_error_handler`, for the endpoints taking arbitrary json
+fn json_payload_error_handler(err: JsonPayloadError, _req: &HttpRequest) -> actix_web::Error {
+    let message = match &err {
+        JsonPayloadError::OverflowKnownLength { length, limit } => format!(
+            "request body of {} bytes exceeds the limit of {} bytes",
+            length, limit
+        ),
+        JsonPayloadError::Overflow { limit } => {
+            format!("request body exceeds the limit of {} bytes", limit)
+        }
+        _ => return err.into(),
+    };
+    log::error!("{}", message);
+    let response = HttpResponse::PayloadTooLarge()
+        .append_header((CONTENT_TYPE, "text/plain"))
+        .body(message);
+    InternalError::from_response(err, response).into()
+}
+
+/// Check an output payload is 0x prefixed hex, so a malformed one is answered with the
+/// position of the offending character instead of failing in the rollup device write.
+/// Strict checking also rejects uppercase hex digits
+fn check_payload(output: &str, payload: &str, hex_strict: bool) -> Result<(), HttpResponse> {
+    let error_message = match payload.strip_prefix("0x") {
+        None => format!("{} payload must start with 0x", output),
+        Some(digits) => match digits
+            .char_indices()
+            .find(|(_, c)| !c.is_ascii_hexdigit() || (hex_strict && c.is_ascii_uppercase()))
+        {
+            Some((index, c)) if c.is_ascii_hexdigit() => format!(
+                "{} payload has uppercase hex character {:?} at index {}, only lowercase is accepted",
+                output,
+                c,
+                index + 2
+            ),
+            Some((index, c)) => format!(
+                "{} payload has invalid hex character {:?} at index {}",
+                output,
+                c,
+                index + 2
+            ),
+            None if digits.len() % 2 != 0 => {
+                format!("{} payload has an odd number of hex digits", output)
+            }
+            None => return Ok(()),
+        },
+    };
+    log::error!("{}", &error_message);
+    Err(HttpResponse::BadRequest()
+        .append_header((CONTENT_TYPE, "text/plain"))
+        .body(error_message))
+}
+
+/// Check the EIP-55 checksum of a mixed-case 0x prefixed address: a letter is uppercase when
+/// the matching nibble of the keccak256 hash of the lowercase address is 8 or more
+fn check_address_checksum(field: &str, address: &str) -> Result<(), String> {
+    let digits = address.trim_start_matches("0x");
+    if digits == digits.to_lowercase() || digits == digits.to_uppercase() {
+        return Ok(());
+    }
+    let hash = hash::keccak256(digits.to_lowercase().as_bytes());
+    let checksummed: String = digits
+        .chars()
+        .enumerate()
+        .map(|(index, c)| {
+            let nibble = (hash[index / 2] >> (4 * (1 - index % 2))) & 0xf;
+            match nibble >= 8 {
+                true => c.to_ascii_uppercase(),
+                false => c.to_ascii_lowercase(),
+            }
+        })
+        .collect();
+    if checksummed != digits {
+        return Err(format!(
+            "{} {} fails the EIP-55 checksum, expected 0x{}",
+            field, address, checksummed
+        ));
+    }
+    Ok(())
+}
+
+/// Header with the errno of a failed rollup device call
+const ROLLUP_ERRNO: &str = "X-Rollup-Errno";
+
+/// Start the reply to a failed rollup device write, with the errno of the device call if
+/// the error comes from one, so clients need not parse the body
+fn device_error_response(e: &(dyn std::error::Error + 'static)) -> HttpResponseBuilder {
+    let mut response = HttpResponse::BadRequest();
+    response.append_header((CONTENT_TYPE, "text/plain"));
+    if let Some(errno) = e.downcast_ref::<RollupError>().and_then(RollupError::errno) {
+        response.append_header((ROLLUP_ERRNO, errno.to_string()));
+    }
+    response
+}
+
+/// Reply sent when the rollup device cannot be written to for now
+fn busy_response(message: String) -> HttpResponse {
+    log::warn!("{}", &message);
+    HttpResponse::ServiceUnavailable()
+        .append_header((RETRY_AFTER, "1"))
+        .append_header((CONTENT_TYPE, "text/plain"))
+        .body(message)
+}
+
+/// Reply sent when the DApp exceeds the configured number of outputs between finish requests
+fn output_limit_response() -> HttpResponse {
+    log::error!("maximum number of pending outputs reached");
+    HttpResponse::TooManyRequests()
+        .append_header((CONTENT_TYPE, "text/plain"))
+        .body("maximum number of outputs before finish reached")
+}
+
+/// Readiness file for orchestrators that watch the filesystem, removed when dropped
+struct ReadyFile {
+    path: String,
+    created: bool,
+}
+
+impl ReadyFile {
+    fn new(path: String) -> Self {
+        ReadyFile {
+            path,
+            created: false,
+        }
+    }
+
+    fn create(&mut self) {
+        if self.created {
+            return;
+        }
+        match std::fs::write(&self.path, std::process::id().to_string()) {
+            Ok(_) => {
+                log::info!("ready file `{}` created", self.path);
+                self.created = true;
+            }
+            Err(e) => log::error!("unable to create ready file `{}`: {}", self.path, e),
+        }
+    }
+}
+
+impl Drop for ReadyFile {
+    fn drop(&mut self) {
+        if self.created {
+            if let Err(e) = std::fs::remove_file(&self.path) {
+                log::error!("unable to remove ready file `{}`: {}", self.path, e);
+            }
+        }
+    }
+}
+
+/// Whether the rollup device can take a finish request: set once the server runs with
+/// the opened device, cleared when an exception is thrown
+#[derive(Default)]
+struct Readiness(AtomicBool);
+
+#[derive(Default)]
+struct ActiveConnections(AtomicUsize);
+
+/// Counts a connection as active for as long as it lives
+struct ConnectionGuard(Data<ActiveConnections>);
+
+impl ConnectionGuard {
+    fn new(active_connections: Data<ActiveConnections>) -> Self {
+        active_connections.0.fetch_add(1, Ordering::Relaxed);
+        ConnectionGuard(active_connections)
+    }
+}
+
+impl Drop for ConnectionGuard {
+    fn drop(&mut self) {
+        self.0 .0.fetch_sub(1, Ordering::Relaxed);
+    }
+}
+
+/// Output accepted by the asynchronous output mode, written by a background task
+enum QueuedOutput {
+    Voucher(Voucher),
+    DelegateCallVoucher(DelegateCallVoucher),
+    Notice(Notice),
+    Report(Report),
+}
+
+impl QueuedOutput {
+    fn kind(&self) -> &'static str {
+        match self {
+            QueuedOutput::Voucher(_) => "voucher",
+            QueuedOutput::DelegateCallVoucher(_) => "delegate call voucher",
+            QueuedOutput::Notice(_) => "notice",
+            QueuedOutput::Report(_) => "report",
+        }
+    }
+}
+
+#[derive(Debug, Clone, Serialize)]
+#[serde(tag = "status", rename_all = "snake_case")]
+enum OutputStatus {
+    Pending,
+    Written {
+        /// Index of vouchers and notices, reports have none
+        #[serde(skip_serializing_if = "Option::is_none")]
+        index: Option<u64>,
+    },
+    Failed {
+        error: String,
+    },
+}
+
+/// Outputs waiting to be written and the status of the most recent tickets
+struct OutputQueue {
+    pending: VecDeque<(u64, QueuedOutput)>,
+    max_pending: usize,
+    statuses: HashMap<u64, OutputStatus>,
+    next_ticket: u64,
+}
+
+impl OutputQueue {
+    fn new(max_pending: usize) -> Self {
+        Self {
+            pending: VecDeque::new(),
+            max_pending,
+            statuses: HashMap::new(),
+            next_ticket: 0,
+        }
+    }
+
+    /// Queue an output, returning its ticket, or `None` when the queue is full
+    fn push(&mut self, output: QueuedOutput) -> Option<u64> {
+        if self.pending.len() >= self.max_pending {
+            return None;
+        }
+        let ticket = self.next_ticket;
+        self.next_ticket += 1;
+        // Tickets are sequential, so the oldest one kept is the first to forget
+        if let Some(oldest) = ticket.checked_sub(OUTPUT_STATUS_HISTORY as u64) {
+            self.statuses.remove(&oldest);
+        }
+        self.statuses.insert(ticket, OutputStatus::Pending);
+        self.pending.push_back((ticket, output));
+        Some(ticket)
+    }
+
+    fn set_status(&mut self, ticket: u64, status: OutputStatus) {
+        if let Some(current) = self.statuses.get_mut(&ticket) {
+            *current = status;
+        }
+    }
+
+    fn status(&self, ticket: u64) -> Option<&OutputStatus> {
+        self.statuses.get(&ticket)
+    }
+}
+
+fn queue_output(context: &mut Context, output: QueuedOutput) -> HttpResponse {
+    let kind = output.kind();
+    match context.output_queue.push(output) {
+        Some(ticket) => {
+            log::debug!("{} queued with ticket {}", kind, ticket);
+            context.output_queue_ready.notify_one();
+            HttpResponse::Accepted().json(TicketResponse { ticket })
+        }
+        None => output_queue_full_response(),
+    }
+}
+
+/// Queue a batch of outputs, all of them or none if the queue cannot hold them all
+fn queue_outputs(context: &mut Context, outputs: Vec<QueuedOutput>) -> HttpResponse {
+    if context.output_queue.pending.len() + outputs.len() > context.output_queue.max_pending {
+        return output_queue_full_response();
+    }
+    let tickets: Vec<u64> = outputs
+        .into_iter()
+        .filter_map(|output| context.output_queue.push(output))
+        .collect();
+    log::debug!("batch of {} outputs queued", tickets.len());
+    context.output_queue_ready.notify_one();
+    HttpResponse::Accepted().json(TicketsResponse { tickets })
+}
+
+fn output_queue_full_response() -> HttpResponse {
+    log::error!("output queue is full");
+    HttpResponse::ServiceUnavailable()
+        .append_header((CONTENT_TYPE, "text/plain"))
+        .body("output queue is full")
+}
+
+/// Write the queued outputs whenever new ones arrive, until the server drops the context
+fn spawn_output_writer(context: std::sync::Weak<SharedContext>, output_queue_ready: Arc<Notify>) {
+    tokio::spawn(async move {
+        loop {
+            output_queue_ready.notified().await;
+            match context.upgrade() {
+                Some(context) => context.lock().await.flush_output_queue().await,
+                None => break,
+            }
+        }
+    });
+}
+
+/// Responses of the gio domains declared deterministic, kept across advances
+struct GioCache {
+    entries: HashMap<(u16, [u8; 32]), (Instant, GIOResponse)>,
+    max_entries: usize,
+    ttl: Option<Duration>,
+}
+
+impl GioCache {
+    fn new(max_entries: usize, ttl: Option<Duration>) -> Self {
+        GioCache {
+            entries: HashMap::new(),
+            max_entries,
+            ttl,
+        }
+    }
+
+    fn key(request: &GIORequest) -> (u16, [u8; 32]) {
+        (request.domain, hash::keccak256(request.id.as_bytes()))
+    }
+
+    fn get(&mut self, request: &GIORequest) -> Option<GIOResponse> {
+        let key = Self::key(request);
+        let (inserted, response) = self.entries.get(&key)?;
+        if matches!(self.ttl, Some(ttl) if inserted.elapsed() > ttl) {
+            self.entries.remove(&key);
+            return None;
+        }
+        Some(response.clone())
+    }
+
+    fn insert(&mut self, request: &GIORequest, response: GIOResponse) {
+        if self.max_entries == 0 {
+            return;
+        }
+        if self.entries.len() >= self.max_entries {
+            // Evict the oldest response
+            if let Some(oldest) = self
+                .entries
+                .iter()
+                .min_by_key(|(_, (inserted, _))| *inserted)
+                .map(|(key, _)| *key)
+            {
+                self.entries.remove(&oldest);
+            }
+        }
+        self.entries
+            .insert(Self::key(request), (Instant::now(), response));
+    }
+}
+
+/// Advance payloads left out of finish responses, kept for download until they expire or
+/// newer ones need their space
+struct PayloadDownloads {
+    entries: VecDeque<(u64, Instant, Bytes)>,
+    total_bytes: usize,
+    max_bytes: usize,
+    ttl: Duration,
+    next_id: u64,
+}
+
+impl PayloadDownloads {
+    fn new(max_bytes: usize, ttl: Duration) -> Self {
+        PayloadDownloads {
+            entries: VecDeque::new(),
+            total_bytes: 0,
+            max_bytes,
+            ttl,
+            next_id: 0,
+        }
+    }
+
+    /// Keep a payload for download, returning its id, or `None` when it alone exceeds the bound
+    fn insert(&mut self, payload: Bytes) -> Option<u64> {
+        if payload.len() > self.max_bytes {
+            return None;
+        }
+        self.expire();
+        while self.total_bytes + payload.len() > self.max_bytes {
+            // Ids are sequential, so the front entry is the oldest
+            let (_, _, evicted) = self.entries.pop_front()?;
+            self.total_bytes -= evicted.len();
+        }
+        let id = self.next_id;
+        self.next_id += 1;
+        self.total_bytes += payload.len();
+        self.entries.push_back((id, Instant::now(), payload));
+        Some(id)
+    }
+
+    fn get(&mut self, id: u64) -> Option<Bytes> {
+        self.expire();
+        self.entries
+            .iter()
+            .find(|(entry_id, _, _)| *entry_id == id)
+            .map(|(_, _, payload)| payload.clone())
+    }
+
+    fn expire(&mut self) {
+        while let Some((_, inserted, _)) = self.entries.front() {
+            if inserted.elapsed() <= self.ttl {
+                break;
+            }
+            if let Some((_, _, expired)) = self.entries.pop_front() {
+                self.total_bytes -= expired.len();
+            }
+        }
+    }
+}
+
+/// Context shared by the workers
+struct SharedContext {
+    context: Mutex<Context>,
+    /// Copy of the configuration of the context, which never changes, read without the lock
+    config: Config,
+}
+
+impl SharedContext {
+    fn new(context: Context) -> Self {
+        SharedContext {
+            config: context.config.clone(),
+            context: Mutex::new(context),
+        }
+    }
+
+    async fn lock(&self) -> MutexGuard<'_, Context> {
+        self.context.lock().await
+    }
+
+    /// Lock the context for a write, answering with a 503 and Retry-After if it stays held
+    /// beyond `lock_wait_timeout_ms`
+    async fn lock_for_write(&self) -> Result<MutexGuard<'_, Context>, HttpResponse> {
+        let Some(timeout) = self.config.lock_wait_timeout_ms else {
+            return Ok(self.context.lock().await);
+        };
+        tokio::time::timeout(Duration::from_millis(timeout), self.context.lock())
+            .await
+            .map_err(|_| busy_response(format!("server context still busy after {} ms", timeout)))
+    }
+
+    /// Whether a request carries the admin token as a bearer token, checked without the lock
+    fn admin_authorized(&self, req: &HttpRequest) -> bool {
+        let token = req
+            .headers()
+            .get(AUTHORIZATION)
+            .and_then(|value| value.to_str().ok())
+            .and_then(|value| value.strip_prefix("Bearer "));
+        match (token, self.config.admin_token.as_deref()) {
+            (Some(token), Some(admin_token)) => constant_time_eq(token, admin_token),
+            _ => false,
+        }
+    }
+}
+
+/// Compare secrets in a time depending only on their lengths, not on where they differ
+fn constant_time_eq(a: &str, b: &str) -> bool {
+    a.len() == b.len()
+        && a.bytes()
+            .zip(b.bytes())
+            .fold(0, |difference, (x, y)| difference | (x ^ y))
+            == 0
+}
+
+struct Context {
+    pub rollup_fd: Arc<Mutex<RollupFd>>,
+    pub config: Config,
+    /// Number of vouchers, notices and reports written since the last finish
+    pub pending_outputs: usize,
+    /// Indices of the vouchers and notices written since the last finish
+    pub output_indices: Vec<u64>,
+    /// Block timestamp of the last advance request, in seconds
+    pub last_block_timestamp: Option<u64>,
+    /// Block number of the last advance request
+    pub last_block_number: Option<u64>,
+    /// Input index of the advance being processed, none while processing an inspect
+    pub current_input_index: Option<u64>,
+    /// Payloads of the reports written during the inspect being processed, none otherwise
+    pub inspect_reports: Option<Vec<String>>,
+    pub ready_file: Option<ReadyFile>,
+    /// Durations of the last finish requests, in milliseconds
+    pub finish_latencies: VecDeque<u64>,
+    pub gio_http_proxies: HashMap<u16, reqwest::Url>,
+    pub gio_cache: GioCache,
+    pub http_client: reqwest::Client,
+    pub output_queue: OutputQueue,
+    /// Indices of the vouchers and notices of the current request, by idempotency key
+    pub emitted_outputs: HashMap<String, u64>,
+    pub output_sequence_file: Option<Arc<OutputSequenceFile>>,
+    /// Version of the last snapshot of the emitted outputs taken for the sequence file
+    pub output_sequence_version: u64,
+    /// Wakes the task writing the queued outputs
+    pub output_queue_ready: Arc<Notify>,
+    pub output_validator: Option<OutputValidator>,
+    pub payload_downloads: PayloadDownloads,
+    /// Sequence number expected of the next notice posted at `/notice` in the current request
+    pub next_notice_sequence: u64,
+    /// Finish that timed out still waiting for the next request on the rollup device
+    pub pending_finish: Option<DeviceFinish>,
+    /// Whether a finish request is waiting for the next request, without holding the context
+    pub finishing: bool,
+    /// Whether the last request read from the rollup device was answered with an error instead
+    /// of being passed to the DApp, for the next finish to reject it
+    pub unseen_input: bool,
+    /// Number of vouchers and notices written in the dry run mode
+    pub dry_run_outputs: u64,
+}
+
+impl Context {
+    /// Run the output validator of the embedder, if any, on a hex encoded payload
+    fn validate_output(&self, kind: OutputKind, payload: &str) -> Result<(), HttpResponse> {
+        let Some(validator) = &self.output_validator else {
+            return Ok(());
+        };
+        let decoded = hex::decode(payload.trim_start_matches("0x")).unwrap_or_default();
+        validator(kind, &decoded).map_err(|message| {
+            log::error!(
+                "{} rejected by the output validator: {}",
+                kind.name(),
+                message
+            );
+            HttpResponse::BadRequest()
+                .append_header((CONTENT_TYPE, "text/plain"))
+                .body(format!("{} rejected: {}", kind.name(), message))
+        })
+    }
+
+    /// Reject with a 409 a notice whose sequence number, when given, is not the next one
+    fn check_notice_sequence(&self, sequence: Option<u64>) -> Result<(), HttpResponse> {
+        let expected_sequence = self.next_notice_sequence;
+        match sequence.filter(|sequence| *sequence != expected_sequence) {
+            Some(sequence) => {
+                let message = format!(
+                    "notice sequence {} is out of order, expected {}",
+                    sequence, expected_sequence
+                );
+                log::error!("{}", &message);
+                Err(HttpResponse::Conflict()
+                    .append_header((CONTENT_TYPE, "text/plain"))
+                    .body(message))
+            }
+            None => Ok(()),
+        }
+    }
+
+    fn output_limit_reached(&self) -> bool {
+        self.output_limit_exceeded_by(1)
+    }
+
+    /// Whether writing `outputs` more outputs would go beyond `max_pending_outputs`, counting
+    /// the queued ones and the notices mirroring reports
+    fn output_limit_exceeded_by(&self, outputs: usize) -> bool {
+        let queued_outputs: usize = self
+            .output_queue
+            .pending
+            .iter()
+            .map(|(_, output)| match output {
+                QueuedOutput::Report(_) => self.report_outputs(),
+                _ => 1,
+            })
+            .sum();
+        let pending_outputs = self.pending_outputs + queued_outputs;
+        matches!(self.config.max_pending_outputs, Some(max) if pending_outputs + outputs > max)
+    }
+
+    /// Outputs a report amounts to, with the notice mirroring it if enabled
+    fn report_outputs(&self) -> usize {
+        match self.config.mirror_reports_to_notices {
+            true => 2,
+            false => 1,
+        }
+    }
+
+    /// Describe the anomalies of the metadata of an advance, hinting at a misconfigured device
+    fn advance_metadata_anomalies(&self, metadata: &AdvanceMetadata) -> Vec<String> {
+        let mut anomalies = Vec::new();
+        if metadata
+            .msg_sender
+            .trim_start_matches("0x")
+            .bytes()
+            .all(|b| b == b'0')
+        {
+            anomalies.push("msg_sender is the zero address".to_string());
+        }
+        if let Some(last_block_number) = self.last_block_number {
+            if metadata.block_number < last_block_number {
+                anomalies.push(format!(
+                    "block_number {} is lower than the block_number {} of the previous advance",
+                    metadata.block_number, last_block_number
+                ));
+            }
+        }
+        anomalies
+    }
+
+    /// Write an output of the asynchronous output mode as the output endpoints do
+    async fn write_queued_output(
+        &mut self,
+        output: &mut QueuedOutput,
+    ) -> Result<Option<u64>, Box<dyn std::error::Error>> {
+        let index = if self.config.dry_run {
+            log::info!(
+                "dry run, not writing {} to the rollup device",
+                output.kind()
+            );
+            match output {
+                QueuedOutput::Report(_) => None,
+                _ => Some(self.next_dry_run_index()),
+            }
+        } else {
+            let rollup_fd = self.rollup_fd.lock().await;
+            match output {
+                QueuedOutput::Voucher(queued_voucher) => {
+                    Some(rollup::rollup_write_voucher(&rollup_fd, queued_voucher)?)
+                }
+                QueuedOutput::DelegateCallVoucher(queued_voucher) => Some(
+                    rollup::rollup_write_delegate_call_voucher(&rollup_fd, queued_voucher)?,
+                ),
+                QueuedOutput::Notice(queued_notice) => {
+                    Some(rollup::rollup_write_notice(&rollup_fd, queued_notice)?)
+                }
+                QueuedOutput::Report(queued_report) => {
+                    rollup::rollup_write_report(&rollup_fd, queued_report)?;
+                    None
+                }
+            }
+        };
+        self.pending_outputs += 1;
+        if let Some(index) = index {
+            self.output_indices.push(index);
+            if self.config.log_output_indices {
+                log::info!("{} written with index {}", output.kind(), index);
+            }
+        }
+        if let QueuedOutput::Report(queued_report) = output {
+            self.record_inspect_report(&queued_report.payload);
+            if self.config.mirror_reports_to_notices {
+                self.mirror_report(&queued_report.payload).await?;
+            }
+        }
+        Ok(index)
+    }
+
+    /// Write the queued outputs in the order they were queued. While a finish waits for the
+    /// next request they stay queued, to be written before the next finish
+    async fn flush_output_queue(&mut self) {
+        if self.awaiting_next_request() {
+            return;
+        }
+        while let Some((ticket, mut output)) = self.output_queue.pending.pop_front() {
+            let status = match self.write_queued_output(&mut output).await {
+                Ok(index) => OutputStatus::Written { index },
+                Err(e) => {
+                    log::error!(
+                        "unable to insert queued {} {}, error details: '{}'",
+                        output.kind(),
+                        ticket,
+                        e
+                    );
+                    OutputStatus::Failed {
+                        error: e.to_string(),
+                    }
+                }
+            };
+            self.output_queue.set_status(ticket, status);
+        }
+    }
+
+    /// Whether a finish holds the rollup device until the next request, leaving no
+    /// current request to write outputs to
+    fn awaiting_next_request(&self) -> bool {
+        self.finishing || self.pending_finish.is_some()
+    }
+
+    /// Lock the rollup device for a write, answering with a 503 and Retry-After while a finish
+    /// waits for the next request, or if the device stays held beyond `lock_wait_timeout_ms`
+    async fn lock_rollup_fd(&self) -> Result<MutexGuard<'_, RollupFd>, HttpResponse> {
+        if self.awaiting_next_request() {
+            return Err(busy_response(String::from(
+                "a finish request is waiting for the next request on the rollup device",
+            )));
+        }
+        let Some(timeout) = self.config.lock_wait_timeout_ms else {
+            return Ok(self.rollup_fd.lock().await);
+        };
+        match tokio::time::timeout(Duration::from_millis(timeout), self.rollup_fd.lock()).await {
+            Ok(rollup_fd) => Ok(rollup_fd),
+            Err(_) => Err(busy_response(format!(
+                "rollup device still busy after {} ms",
+                timeout
+            ))),
+        }
+    }
+
+    /// Run a write on the locked rollup device, or log it and answer with `dry_run_result`
+    /// without touching the device in the dry run mode
+    async fn write_device<T>(
+        &self,
+        output: &str,
+        dry_run_result: T,
+        write: impl FnOnce(&RollupFd) -> Result<T, Box<dyn std::error::Error>>,
+    ) -> Result<Result<T, Box<dyn std::error::Error>>, HttpResponse> {
+        if self.config.dry_run {
+            log::info!("dry run, not writing {} to the rollup device", output);
+            return Ok(Ok(dry_run_result));
+        }
+        let rollup_fd = self.lock_rollup_fd().await?;
+        Ok(write(&rollup_fd))
+    }
+
+    /// Index of the next voucher or notice in the dry run mode. Like the indices of the device
+    /// they keep increasing across requests, counted since the server started
+    fn next_dry_run_index(&mut self) -> u64 {
+        let index = self.dry_run_outputs;
+        if self.config.dry_run {
+            self.dry_run_outputs += 1;
+        }
+        index
+    }
+
+    fn emitted_output(&self, idempotency_key: Option<&str>) -> Option<u64> {
+        idempotency_key.and_then(|key| self.emitted_outputs.get(key).copied())
+    }
+
+    /// Record the index of an output by its idempotency key. The snapshot of the outputs
+    /// returned is written to the sequence file by the caller, once the context is released
+    fn record_emitted_output(
+        &mut self,
+        idempotency_key: Option<String>,
+        index: u64,
+    ) -> Option<OutputSequenceWrite> {
+        let key = idempotency_key?;
+        self.emitted_outputs.insert(key, index);
+        self.emitted_outputs_snapshot()
+    }
+
+    fn clear_emitted_outputs(&mut self) -> Option<OutputSequenceWrite> {
+        if self.emitted_outputs.is_empty() {
+            return None;
+        }
+        self.emitted_outputs.clear();
+        self.emitted_outputs_snapshot()
+    }
+
+    fn emitted_outputs_snapshot(&mut self) -> Option<OutputSequenceWrite> {
+        let file = self.output_sequence_file.clone()?;
+        match serde_json::to_vec(&self.emitted_outputs) {
+            Ok(contents) => {
+                self.output_sequence_version += 1;
+                Some(OutputSequenceWrite {
+                    file,
+                    version: self.output_sequence_version,
+                    contents,
+                })
+            }
+            Err(e) => {
+                log::error!("unable to encode output sequence file {}: {}", file.path, e);
+                None
+            }
+        }
+    }
+
+    fn record_inspect_report(&mut self, payload: &str) {
+        if let Some(reports) = self.inspect_reports.as_mut() {
+            reports.push(payload.to_string());
+        }
+    }
+
+    /// Write a notice with the payload of a report just written
+    async fn mirror_report(&mut self, payload: &str) -> Result<(), Box<dyn std::error::Error>> {
+        let mut mirrored_notice = Notice {
+            payload: payload.to_string(),
+            sequence: None,
+        };
+        let notice_index = if self.config.dry_run {
+            log::info!("dry run, not writing mirrored notice to the rollup device");
+            self.next_dry_run_index()
+        } else {
+            rollup::rollup_write_notice(&*self.rollup_fd.lock().await, &mut mirrored_notice)?
+        };
+        self.pending_outputs += 1;
+        self.output_indices.push(notice_index);
+        if self.config.log_output_indices {
+            log::info!("notice written with index {}", notice_index);
+        }
+        Ok(())
+    }
+
+    fn record_finish_latency(&mut self, millis: u64) {
+        if self.finish_latencies.len() == FINISH_LATENCY_HISTORY {
+            self.finish_latencies.pop_front();
+        }
+        self.finish_latencies.push_back(millis);
+    }
 }
@@ -14,7 +14,25 @@
 // limitations under the License.
 //
 
+pub mod abi;
 pub mod config;
 pub mod dapp_process;
+pub mod hash;
 pub mod http_service;
+pub mod metrics;
 pub mod rollup;
+
+/// Version and build information logged at startup
+pub fn build_banner() -> String {
+    let features = match env!("BUILD_FEATURES") {
+        "" => "none",
+        features => features,
+    };
+    format!(
+        "rollup-http-server {} (commit {}, {} build, features: {})",
+        env!("CARGO_PKG_VERSION"),
+        env!("BUILD_GIT_COMMIT"),
+        env!("BUILD_PROFILE"),
+        features
+    )
+}
@@ -14,12 +14,16 @@
 // limitations under the License.
 //
 
-use std::io::ErrorKind;
+use std::io::{ErrorKind, Write};
 use std::sync::Arc;
 
 use async_mutex::Mutex;
 use getopts::{Options, ParsingStyle};
-use rollup_http_server::{config::Config, dapp_process, http_service, rollup::RollupFd};
+use rollup_http_server::{
+    config::{Config, LogTimestampFormat},
+    dapp_process, http_service,
+    rollup::{self, RollupFd},
+};
 use tokio::sync::Notify;
 
 fn print_usage(program: &str, opts: Options) {
@@ -46,8 +50,260 @@ async fn main() -> std::io::Result<()> {
         "Address to listen (default: 127.0.0.1:5004)",
         "",
     );
+    opts.optopt(
+        "",
+        "unix-socket",
+        "Unix domain socket to listen on instead of --address",
+        "",
+    );
     opts.optopt("", "dapp", "Dapp address (default: 127.0.0.1:5003)", "");
+    opts.optopt(
+        "",
+        "config",
+        "TOML config file, overridden by ROLLUP_HTTP_SERVER_* environment variables and options",
+        "FILE",
+    );
     opts.optflag("", "verbose", "print more info about application execution");
+    opts.optflag(
+        "",
+        "tcp-nodelay",
+        "disable Nagle's algorithm, lowering latency for more packets",
+    );
+    opts.optopt(
+        "",
+        "connection-idle-timeout-secs",
+        "Seconds a connection may stay idle between requests before it is closed (default: 5)",
+        "",
+    );
+    opts.optopt(
+        "",
+        "server-header",
+        "Server header of the responses, removed when empty (default: none)",
+        "",
+    );
+    opts.optmulti(
+        "",
+        "allowed-methods",
+        "Comma separated methods allowed on a route, none disabling it, may be repeated",
+        "ROUTE=METHODS",
+    );
+    opts.optopt(
+        "",
+        "normalize-path",
+        "Rewrite request paths before routing: none, trim or merge (default: none)",
+        "",
+    );
+    opts.optopt(
+        "",
+        "slow-start-secs",
+        "Ramp up the accepted request rate during this many seconds after bind",
+        "",
+    );
+    opts.optopt(
+        "",
+        "slow-start-rate",
+        "Requests a second accepted right after bind with --slow-start-secs (default: 10)",
+        "",
+    );
+    opts.optopt(
+        "",
+        "lock-wait-timeout-ms",
+        "Answer write requests with a 503 when the server or the rollup device stays busy this long (default: wait)",
+        "",
+    );
+    opts.optopt(
+        "",
+        "finish-timeout-ms",
+        "Answer finish requests with a 504 when no next request arrives this long (default: wait)",
+        "",
+    );
+    opts.optopt(
+        "",
+        "max-pending-outputs",
+        "Maximum number of outputs written between finish requests (default: unlimited)",
+        "",
+    );
+    opts.optopt(
+        "",
+        "ready-file",
+        "File created when the dapp is ready to process requests",
+        "",
+    );
+    opts.optflag(
+        "",
+        "require-supported-device",
+        "exit at startup if the rollup device is not supported",
+    );
+    opts.optflag(
+        "",
+        "debug-endpoints",
+        "enable the /debug endpoints used to troubleshoot the server",
+    );
+    opts.optflag(
+        "",
+        "enable-metrics",
+        "expose request counters and latencies in the Prometheus format at /metrics",
+    );
+    opts.optopt(
+        "",
+        "admin-token",
+        "Bearer token of the /admin endpoints, not served without one",
+        "",
+    );
+    opts.optflag(
+        "",
+        "log-output-indices",
+        "log the index of each voucher and notice written",
+    );
+    opts.optflag(
+        "",
+        "finish-output-indices",
+        "include the indices of the outputs of the previous request in finish responses",
+    );
+    opts.optflag(
+        "",
+        "mirror-reports-to-notices",
+        "write a notice with the same payload after each report",
+    );
+    opts.optflag(
+        "",
+        "strict-advance-metadata",
+        "reject advances with a zero msg_sender or a decreasing block number",
+    );
+    opts.optflag(
+        "",
+        "enforce-checksum-addresses",
+        "reject vouchers whose mixed-case destination fails the EIP-55 checksum",
+    );
+    opts.optflag(
+        "",
+        "hex-strict",
+        "reject output payloads with uppercase hex digits",
+    );
+    opts.optflag(
+        "",
+        "async-outputs",
+        "queue vouchers, notices and reports, answering with a ticket polled at /output_status",
+    );
+    opts.optflag(
+        "",
+        "dry-run",
+        "log outputs, gio and finish requests instead of sending them to the rollup device",
+    );
+    opts.optopt(
+        "",
+        "dry-run-finish-interval-ms",
+        "Delay before answering finish requests with --dry-run (default: 1000)",
+        "",
+    );
+    opts.optopt(
+        "",
+        "async-output-queue-size",
+        "Maximum number of outputs waiting to be written with --async-outputs (default: 64)",
+        "",
+    );
+    opts.optopt(
+        "",
+        "output-sequence-file",
+        "File keeping the outputs written with an Idempotency-Key header across restarts",
+        "",
+    );
+    opts.optopt(
+        "",
+        "max-advance-payload-bytes",
+        "Largest advance payload passed to the dapp (default: unlimited)",
+        "",
+    );
+    opts.optopt(
+        "",
+        "payload-download-threshold",
+        "Advance payloads larger than this many bytes are downloaded from /advance_payload instead of inlined (default: always inlined)",
+        "",
+    );
+    opts.optopt(
+        "",
+        "payload-download-max-bytes",
+        "Total size of the advance payloads kept for download (default: 67108864)",
+        "",
+    );
+    opts.optopt(
+        "",
+        "payload-download-ttl-secs",
+        "Seconds an advance payload is kept for download (default: 600)",
+        "",
+    );
+    opts.optopt(
+        "",
+        "max-json-body-size",
+        "Largest json request body in bytes, binary bodies excluded (default: 32768)",
+        "",
+    );
+    opts.optopt(
+        "",
+        "max-notice-size",
+        "Largest request body of the notice endpoints in bytes (default: --max-json-body-size)",
+        "",
+    );
+    opts.optopt(
+        "",
+        "max-report-size",
+        "Largest request body of the report endpoints in bytes (default: --max-json-body-size)",
+        "",
+    );
+    opts.optopt(
+        "",
+        "max-uptime-secs",
+        "Shut down gracefully after running for this many seconds (default: never)",
+        "",
+    );
+    opts.optopt(
+        "",
+        "watchdog-interval-secs",
+        "Shut down when a health check the server sends itself every this many seconds fails",
+        "",
+    );
+    opts.optopt(
+        "",
+        "log-timestamp-format",
+        "Timestamp in log lines: none, rfc3339 or epoch-millis (default: none)",
+        "",
+    );
+    opts.optmulti(
+        "",
+        "gio-http-domain",
+        "Forward gio requests of a domain to an external http service, may be repeated",
+        "DOMAIN=URL",
+    );
+    opts.optmulti(
+        "",
+        "gio-domain-timeout",
+        "Timeout in milliseconds of the gio requests of a domain, may be repeated",
+        "DOMAIN=MILLISECONDS",
+    );
+    opts.optopt(
+        "",
+        "gio-timeout",
+        "Timeout in milliseconds of gio requests without a domain timeout (default: none)",
+        "",
+    );
+    opts.optmulti(
+        "",
+        "gio-cache-domain",
+        "Cache the gio responses of a deterministic domain across advances, may be repeated",
+        "DOMAIN",
+    );
+    opts.optopt(
+        "",
+        "gio-cache-max-entries",
+        "Maximum number of cached gio responses (default: 1024)",
+        "",
+    );
+    opts.optopt(
+        "",
+        "gio-cache-ttl",
+        "Seconds a cached gio response stays valid (default: forever)",
+        "",
+    );
     let matches = match opts.parse(&args[1..]) {
         Ok(m) => m,
         Err(e) => {
@@ -65,10 +321,48 @@ async fn main() -> std::io::Result<()> {
     if matches.opt_present("verbose") {
         log_level = "debug";
     }
-    // Set the global log level, disable timestamp
-    env_logger::Builder::from_env(env_logger::Env::default().default_filter_or(log_level))
-        .format_timestamp(None)
-        .init();
+    // Create config, read before the logger is set up as it holds the timestamp format
+    let mut http_config = match matches.opt_str("config") {
+        Some(path) => match Config::from_file(&path) {
+            Ok(config) => config,
+            Err(e) => {
+                eprintln!("error reading config: {}", &e);
+                return Err(e);
+            }
+        },
+        None => Config::new(),
+    };
+    match matches.opt_get("log-timestamp-format") {
+        Ok(Some(format)) => http_config.log_timestamp_format = format,
+        Ok(None) => (),
+        Err(e) => {
+            eprintln!("error parsing arguments: {}", &e);
+            return Err(std::io::Error::new(ErrorKind::InvalidInput, e));
+        }
+    };
+    // Set the global log level and timestamp format
+    let mut logger =
+        env_logger::Builder::from_env(env_logger::Env::default().default_filter_or(log_level));
+    match http_config.log_timestamp_format {
+        LogTimestampFormat::None => logger.format_timestamp(None),
+        LogTimestampFormat::Rfc3339 => logger.format_timestamp_millis(),
+        LogTimestampFormat::EpochMillis => logger.format(|buf, record| {
+            let millis = std::time::SystemTime::now()
+                .duration_since(std::time::UNIX_EPOCH)
+                .unwrap_or_default()
+                .as_millis();
+            writeln!(
+                buf,
+                "[{} {:<5} {}] {}",
+                millis,
+                record.level(),
+                record.target(),
+                record.args()
+            )
+        }),
+    };
+    logger.init();
+    log::info!("{}", rollup_http_server::build_banner());
 
     // Check if there are enough arguments to start the dapp
     if matches.free.is_empty() {
@@ -80,13 +374,9 @@ async fn main() -> std::io::Result<()> {
 
     log::info!("starting http dispatcher service...");
 
-    // Create config
-    let mut http_config = Config::new();
-    {
+    // Options given in the command line override the config file
+    if let Some(address_matches) = matches.opt_str("address") {
         // Parse addresses and ports
-        let address_matches = matches
-            .opt_get_default("address", "127.0.0.1:5004".to_string())
-            .unwrap_or_default();
         let mut address = address_matches.split(':');
         http_config.http_address = address.next().expect("address is not valid").to_string();
         http_config.http_port = address
@@ -96,8 +386,216 @@ async fn main() -> std::io::Result<()> {
             .parse::<u16>()
             .unwrap();
     }
+    if let Some(unix_socket) = matches.opt_str("unix-socket") {
+        http_config.unix_socket = Some(unix_socket.into());
+    }
+    http_config.tcp_nodelay |= matches.opt_present("tcp-nodelay");
+    if let Some(connection_idle_timeout_secs) = matches
+        .opt_get("connection-idle-timeout-secs")
+        .expect("connection-idle-timeout-secs is not valid")
+    {
+        http_config.connection_idle_timeout_secs = Some(connection_idle_timeout_secs);
+    }
+    if let Some(server_header) = matches.opt_str("server-header") {
+        http_config.server_header = Some(server_header);
+    }
+    for allowed_methods in matches.opt_strs("allowed-methods") {
+        let (route, methods) = allowed_methods
+            .split_once('=')
+            .expect("allowed-methods is not valid");
+        http_config.allowed_methods.insert(
+            route.to_string(),
+            methods
+                .split(',')
+                .filter(|method| !method.is_empty())
+                .map(str::to_string)
+                .collect(),
+        );
+    }
+    if let Some(path_normalization) = matches
+        .opt_get("normalize-path")
+        .expect("normalize-path is not valid")
+    {
+        http_config.path_normalization = path_normalization;
+    }
+    if let Some(slow_start_secs) = matches
+        .opt_get("slow-start-secs")
+        .expect("slow-start-secs is not valid")
+    {
+        http_config.slow_start_secs = Some(slow_start_secs);
+    }
+    if let Some(slow_start_rate) = matches
+        .opt_get("slow-start-rate")
+        .expect("slow-start-rate is not valid")
+    {
+        http_config.slow_start_rate = slow_start_rate;
+    }
+    if let Some(lock_wait_timeout_ms) = matches
+        .opt_get("lock-wait-timeout-ms")
+        .expect("lock-wait-timeout-ms is not valid")
+    {
+        http_config.lock_wait_timeout_ms = Some(lock_wait_timeout_ms);
+    }
+    if let Some(finish_timeout_ms) = matches
+        .opt_get("finish-timeout-ms")
+        .expect("finish-timeout-ms is not valid")
+    {
+        http_config.finish_timeout_ms = Some(finish_timeout_ms);
+    }
+    if let Some(max_pending_outputs) = matches
+        .opt_get("max-pending-outputs")
+        .expect("max-pending-outputs is not valid")
+    {
+        http_config.max_pending_outputs = Some(max_pending_outputs);
+    }
+    if let Some(ready_file) = matches.opt_str("ready-file") {
+        http_config.ready_file = Some(ready_file);
+    }
+    http_config.require_supported_device |= matches.opt_present("require-supported-device");
+    http_config.debug_endpoints |= matches.opt_present("debug-endpoints");
+    http_config.enable_metrics |= matches.opt_present("enable-metrics");
+    if let Some(admin_token) = matches.opt_str("admin-token") {
+        http_config.admin_token = Some(admin_token);
+    }
+    if let Some(max_uptime_secs) = matches
+        .opt_get("max-uptime-secs")
+        .expect("max-uptime-secs is not valid")
+    {
+        http_config.max_uptime_secs = Some(max_uptime_secs);
+    }
+    if let Some(watchdog_interval_secs) = matches
+        .opt_get("watchdog-interval-secs")
+        .expect("watchdog-interval-secs is not valid")
+    {
+        http_config.watchdog_interval_secs = Some(watchdog_interval_secs);
+    }
+    http_config.log_output_indices |= matches.opt_present("log-output-indices");
+    http_config.finish_output_indices |= matches.opt_present("finish-output-indices");
+    http_config.mirror_reports_to_notices |= matches.opt_present("mirror-reports-to-notices");
+    http_config.strict_advance_metadata |= matches.opt_present("strict-advance-metadata");
+    http_config.enforce_checksum_addresses |= matches.opt_present("enforce-checksum-addresses");
+    http_config.hex_strict |= matches.opt_present("hex-strict");
+    http_config.async_outputs |= matches.opt_present("async-outputs");
+    http_config.dry_run |= matches.opt_present("dry-run");
+    if let Some(interval) = matches
+        .opt_get("dry-run-finish-interval-ms")
+        .expect("dry-run-finish-interval-ms is not valid")
+    {
+        http_config.dry_run_finish_interval_ms = interval;
+    }
+    if let Some(queue_size) = matches
+        .opt_get("async-output-queue-size")
+        .expect("async-output-queue-size is not valid")
+    {
+        http_config.async_output_queue_size = queue_size;
+    }
+    if let Some(output_sequence_file) = matches.opt_str("output-sequence-file") {
+        http_config.output_sequence_file = Some(output_sequence_file);
+    }
+    if let Some(max_advance_payload_bytes) = matches
+        .opt_get("max-advance-payload-bytes")
+        .expect("max-advance-payload-bytes is not valid")
+    {
+        http_config.max_advance_payload_bytes = Some(max_advance_payload_bytes);
+    }
+    if let Some(payload_download_threshold) = matches
+        .opt_get("payload-download-threshold")
+        .expect("payload-download-threshold is not valid")
+    {
+        http_config.payload_download_threshold = Some(payload_download_threshold);
+    }
+    if let Some(payload_download_max_bytes) = matches
+        .opt_get("payload-download-max-bytes")
+        .expect("payload-download-max-bytes is not valid")
+    {
+        http_config.payload_download_max_bytes = payload_download_max_bytes;
+    }
+    if let Some(payload_download_ttl_secs) = matches
+        .opt_get("payload-download-ttl-secs")
+        .expect("payload-download-ttl-secs is not valid")
+    {
+        http_config.payload_download_ttl_secs = payload_download_ttl_secs;
+    }
+    if let Some(max_json_body_size) = matches
+        .opt_get("max-json-body-size")
+        .expect("max-json-body-size is not valid")
+    {
+        http_config.max_json_body_size = max_json_body_size;
+    }
+    if let Some(max_notice_size) = matches
+        .opt_get("max-notice-size")
+        .expect("max-notice-size is not valid")
+    {
+        http_config.max_notice_size = Some(max_notice_size);
+    }
+    if let Some(max_report_size) = matches
+        .opt_get("max-report-size")
+        .expect("max-report-size is not valid")
+    {
+        http_config.max_report_size = Some(max_report_size);
+    }
+    for gio_http_domain in matches.opt_strs("gio-http-domain") {
+        let (domain, url) = gio_http_domain
+            .split_once('=')
+            .expect("gio-http-domain is not valid");
+        http_config.gio_http_domains.insert(
+            domain.parse::<u16>().expect("gio-http-domain is not valid"),
+            url.to_string(),
+        );
+    }
+    for gio_domain_timeout in matches.opt_strs("gio-domain-timeout") {
+        let (domain, timeout) = gio_domain_timeout
+            .split_once('=')
+            .expect("gio-domain-timeout is not valid");
+        http_config.gio_domain_timeouts.insert(
+            domain
+                .parse::<u16>()
+                .expect("gio-domain-timeout is not valid"),
+            timeout
+                .parse::<u64>()
+                .expect("gio-domain-timeout is not valid"),
+        );
+    }
+    if let Some(gio_default_timeout_ms) = matches
+        .opt_get("gio-timeout")
+        .expect("gio-timeout is not valid")
+    {
+        http_config.gio_default_timeout_ms = Some(gio_default_timeout_ms);
+    }
+    for gio_cache_domain in matches.opt_strs("gio-cache-domain") {
+        http_config.gio_cache_domains.insert(
+            gio_cache_domain
+                .parse::<u16>()
+                .expect("gio-cache-domain is not valid"),
+        );
+    }
+    if let Some(max_entries) = matches
+        .opt_get("gio-cache-max-entries")
+        .expect("gio-cache-max-entries is not valid")
+    {
+        http_config.gio_cache_max_entries = max_entries;
+    }
+    if let Some(gio_cache_ttl) = matches
+        .opt_get("gio-cache-ttl")
+        .expect("gio-cache-ttl is not valid")
+    {
+        http_config.gio_cache_ttl = Some(std::time::Duration::from_secs(gio_cache_ttl));
+    }
 
-    let rollup_fd: Arc<Mutex<RollupFd>> = Arc::new(Mutex::new(RollupFd::create().unwrap()));
+    // The dry run mode never uses the rollup device, so it runs where there is none
+    let rollup_fd = if http_config.dry_run {
+        log::info!("dry run, the rollup device is not opened");
+        RollupFd::detached()
+    } else {
+        match rollup::open_rollup_device(http_config.require_supported_device) {
+            Ok(fd) => fd,
+            Err(e) => {
+                log::error!("{}", e);
+                return Err(e);
+            }
+        }
+    };
+    let rollup_fd: Arc<Mutex<RollupFd>> = Arc::new(Mutex::new(rollup_fd));
     let server_ready = Arc::new(Notify::new());
 
     // In another thread, wait until the server is ready and then start the dapp
@@ -110,9 +608,12 @@ async fn main() -> std::io::Result<()> {
         })
     };
 
+    // Actix also stops the server gracefully on SIGINT and SIGTERM
+    let shutdown = Arc::new(Notify::new());
+
     // Open http service
     tokio::select! {
-        result = http_service::run(&http_config, rollup_fd, server_ready) => {
+        result = http_service::run(&http_config, rollup_fd, server_ready, shutdown) => {
             match result {
                 Ok(_) => log::info!("http service terminated successfully"),
                 Err(e) => log::warn!("http service terminated with error: {}", e),
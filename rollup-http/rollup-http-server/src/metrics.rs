@@ -0,0 +1,162 @@
+// Copyright Cartesi and individual authors (see AUTHORS)
+// SPDX-License-Identifier: Apache-2.0
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+// http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+//
+
+//! Request counters and latency histograms of the output and gio endpoints,
+//! rendered in the Prometheus text format. Requests answered with a 4xx or 5xx
+//! status are counted apart from the successful ones.
+
+use std::fmt::Write;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::time::Duration;
+
+/// Upper bounds in seconds of the latency histogram buckets
+const LATENCY_BUCKETS: [f64; 10] = [
+    0.0005, 0.001, 0.0025, 0.005, 0.01, 0.025, 0.05, 0.1, 0.5, 1.0,
+];
+
+/// Handlers whose requests are counted
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Handler {
+    Voucher,
+    Notice,
+    Report,
+    Gio,
+    Exception,
+}
+
+impl Handler {
+    const ALL: [Handler; 5] = [
+        Handler::Voucher,
+        Handler::Notice,
+        Handler::Report,
+        Handler::Gio,
+        Handler::Exception,
+    ];
+
+    /// Handler serving a request path, if its requests are counted
+    pub fn from_path(path: &str) -> Option<Self> {
+        match path {
+            "/voucher" | "/voucher/erc20_transfer" | "/delegate_call_voucher" => {
+                Some(Handler::Voucher)
+            }
+            "/notice" | "/notice/abi" => Some(Handler::Notice),
+            "/report" | "/report/json" | "/report_batch" => Some(Handler::Report),
+            "/gio" | "/gio_binary" => Some(Handler::Gio),
+            "/exception" => Some(Handler::Exception),
+            _ => None,
+        }
+    }
+
+    fn name(&self) -> &'static str {
+        match self {
+            Handler::Voucher => "voucher",
+            Handler::Notice => "notice",
+            Handler::Report => "report",
+            Handler::Gio => "gio",
+            Handler::Exception => "exception",
+        }
+    }
+}
+
+#[derive(Default)]
+struct HandlerMetrics {
+    successes: AtomicU64,
+    errors: AtomicU64,
+    /// Requests per bucket, not cumulative
+    buckets: [AtomicU64; LATENCY_BUCKETS.len()],
+    sum_micros: AtomicU64,
+}
+
+/// Counters shared by all workers, updated without locking
+#[derive(Default)]
+pub struct Metrics {
+    handlers: [HandlerMetrics; Handler::ALL.len()],
+}
+
+impl Metrics {
+    /// Count a request of a handler, whether it failed, and the time it took
+    pub fn record(&self, handler: Handler, latency: Duration, failed: bool) {
+        let metrics = &self.handlers[handler as usize];
+        if failed {
+            metrics.errors.fetch_add(1, Ordering::Relaxed);
+        } else {
+            metrics.successes.fetch_add(1, Ordering::Relaxed);
+        }
+        metrics
+            .sum_micros
+            .fetch_add(latency.as_micros() as u64, Ordering::Relaxed);
+        let seconds = latency.as_secs_f64();
+        if let Some(bucket) = LATENCY_BUCKETS.iter().position(|bound| seconds <= *bound) {
+            metrics.buckets[bucket].fetch_add(1, Ordering::Relaxed);
+        }
+    }
+
+    /// Metrics in the Prometheus text exposition format
+    pub fn render(&self) -> String {
+        let mut text = String::new();
+        for handler in Handler::ALL {
+            let name = handler.name();
+            let metrics = &self.handlers[handler as usize];
+            let _ = writeln!(text, "# TYPE rollup_{}_total counter", name);
+            let _ = writeln!(
+                text,
+                "rollup_{}_total {}",
+                name,
+                metrics.successes.load(Ordering::Relaxed)
+            );
+            let _ = writeln!(text, "# TYPE rollup_{}_errors_total counter", name);
+            let _ = writeln!(
+                text,
+                "rollup_{}_errors_total {}",
+                name,
+                metrics.errors.load(Ordering::Relaxed)
+            );
+        }
+        let _ = writeln!(text, "# TYPE rollup_handler_duration_seconds histogram");
+        for handler in Handler::ALL {
+            let name = handler.name();
+            let metrics = &self.handlers[handler as usize];
+            let mut cumulative = 0;
+            for (bound, count) in LATENCY_BUCKETS.iter().zip(&metrics.buckets) {
+                cumulative += count.load(Ordering::Relaxed);
+                let _ = writeln!(
+                    text,
+                    "rollup_handler_duration_seconds_bucket{{handler=\"{}\",le=\"{}\"}} {}",
+                    name, bound, cumulative
+                );
+            }
+            let total =
+                metrics.successes.load(Ordering::Relaxed) + metrics.errors.load(Ordering::Relaxed);
+            let _ = writeln!(
+                text,
+                "rollup_handler_duration_seconds_bucket{{handler=\"{}\",le=\"+Inf\"}} {}",
+                name, total
+            );
+            let _ = writeln!(
+                text,
+                "rollup_handler_duration_seconds_sum{{handler=\"{}\"}} {}",
+                name,
+                metrics.sum_micros.load(Ordering::Relaxed) as f64 / 1e6
+            );
+            let _ = writeln!(
+                text,
+                "rollup_handler_duration_seconds_count{{handler=\"{}\"}} {}",
+                name, total
+            );
+        }
+        text
+    }
+}
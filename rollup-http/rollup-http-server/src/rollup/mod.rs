@@ -24,7 +24,7 @@ use lazy_static::lazy_static;
 use libc::c_void;
 use regex::Regex;
 use serde::{Deserialize, Serialize};
-use validator::Validate;
+use validator::{Validate, ValidationError};
 
 include!(concat!(env!("OUT_DIR"), "/bindings.rs"));
 
@@ -43,10 +43,19 @@ impl RollupFd {
             }
         }
     }
+
+    /// Handle not backed by a rollup device, for the dry run mode that never uses one.
+    /// Its buffers are empty and the C bindings reject it
+    pub fn detached() -> Self {
+        RollupFd(std::ptr::null_mut())
+    }
 }
 
 impl Drop for RollupFd {
     fn drop(&mut self) {
+        if self.0.is_null() {
+            return;
+        }
         unsafe {
             cmt_rollup_fini(self.0);
             drop(Box::from_raw(self.0));
@@ -60,23 +69,72 @@ unsafe impl Send for RollupFd {}
 pub const REQUEST_TYPE_ADVANCE_STATE: u32 = 0;
 pub const REQUEST_TYPE_INSPECT_STATE: u32 = 1;
 pub const CARTESI_ROLLUP_ADDRESS_SIZE: u32 = 20;
+/// ABI encoding overhead of a voucher, the largest output: selector, destination, value,
+/// payload offset and payload length
+pub const VOUCHER_ABI_OVERHEAD: usize = 4 + 4 * CMT_ABI_U256_LENGTH as usize;
 
 lazy_static! {
     static ref ETH_ADDR_REGEXP: Regex = Regex::new(r"0x[0-9a-fA-F]{1,42}$").unwrap();
-    static ref ETH_U256_REGEXP: Regex = Regex::new(r"0x[0-9a-fA-F]{1,64}$").unwrap();
+    static ref ETH_U256_REGEXP: Regex = Regex::new(r"^0x[0-9a-fA-F]{64}$").unwrap();
+}
+
+/// Check an address is 0x followed by 40 hex digits of either case, telling a wrong
+/// length from invalid characters
+fn validate_address(address: &str) -> Result<(), ValidationError> {
+    let expected_len = CARTESI_ROLLUP_ADDRESS_SIZE as usize * 2 + 2;
+    let error = |code, message: String| {
+        let mut error = ValidationError::new(code);
+        error.message = Some(message.into());
+        Err(error)
+    };
+    let Some(digits) = address.strip_prefix("0x") else {
+        return error("prefix", String::from("must start with 0x"));
+    };
+    if address.len() != expected_len {
+        return error(
+            "length",
+            format!(
+                "must be {} characters long, got {}",
+                expected_len,
+                address.len()
+            ),
+        );
+    }
+    match digits.char_indices().find(|(_, c)| !c.is_ascii_hexdigit()) {
+        Some((index, c)) => error(
+            "hex",
+            format!("invalid hex character {:?} at index {}", c, index + 2),
+        ),
+        None => Ok(()),
+    }
 }
 
 #[derive(Debug, Default)]
 pub struct RollupError {
     message: String,
+    errno: Option<i32>,
 }
 
 impl RollupError {
     pub fn new(message: &str) -> Self {
         RollupError {
             message: String::from(message),
+            errno: None,
+        }
+    }
+
+    /// Error of a libcmt call, which returns the negated errno
+    pub fn from_device(message: &str, res: i32) -> Self {
+        RollupError {
+            message: String::from(message),
+            errno: Some(-res),
         }
     }
+
+    /// Errno of the failed rollup device call, if the error comes from one
+    pub fn errno(&self) -> Option<i32> {
+        self.errno
+    }
 }
 
 impl std::fmt::Display for RollupError {
@@ -128,7 +186,7 @@ impl cmt_abi_u256_t {
         if rc != 0 {
             return Err(hex::FromHexError::InvalidStringLength);
         }
-        return Ok(value);
+        Ok(value)
     }
 }
 
@@ -144,7 +202,7 @@ impl cmt_abi_address_t {
                 CMT_ABI_ADDRESS_LENGTH as usize,
             );
         };
-        return Ok(address);
+        Ok(address)
     }
 }
 
@@ -178,11 +236,11 @@ pub struct AdvanceMetadata {
 impl From<cmt_rollup_advance_t> for AdvanceMetadata {
     fn from(other: cmt_rollup_advance_t) -> Self {
         let mut msg_sender = "0x".to_string();
-        msg_sender.push_str(&hex::encode(&other.msg_sender.data));
+        msg_sender.push_str(&hex::encode(other.msg_sender.data));
         let mut app_contract = "0x".to_string();
-        app_contract.push_str(&hex::encode(&other.app_contract.data));
+        app_contract.push_str(&hex::encode(other.app_contract.data));
         let mut prev_randao = "0x".to_string();
-        prev_randao.push_str(&hex::encode(&other.prev_randao.data));
+        prev_randao.push_str(&hex::encode(other.prev_randao.data));
         AdvanceMetadata {
             chain_id: other.chain_id,
             app_contract,
@@ -234,14 +292,52 @@ pub struct InspectReport {
 #[derive(Debug, Clone, Serialize, Deserialize, Validate)]
 pub struct Notice {
     pub payload: String,
+    /// Position of the notice among those of the current request, checked when given
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub sequence: Option<u64>,
+}
+
+/// Notice whose payload is ABI encoded by the server from an event signature and its arguments
+#[derive(Debug, Clone, Serialize, Deserialize, Validate)]
+pub struct AbiNotice {
+    pub event_signature: String,
+    pub args: Vec<serde_json::Value>,
+    /// Position of the notice among those of the current request, checked as the one of `Notice`
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub sequence: Option<u64>,
+}
+
+/// Voucher transferring ERC-20 tokens, whose calldata is ABI encoded by the server
+#[derive(Debug, Clone, Serialize, Deserialize, Validate)]
+pub struct Erc20Transfer {
+    pub token: String,
+    pub to: String,
+    /// Amount in the token base unit, in decimal or 0x prefixed hex
+    pub amount: String,
+}
+
+/// Reports written by a single request, in order
+#[derive(Debug, Clone, Serialize, Deserialize, Validate)]
+pub struct ReportBatch {
+    pub payloads: Vec<String>,
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize, Validate)]
 pub struct Voucher {
-    #[validate(regex(path = "*ETH_ADDR_REGEXP"))]
+    #[validate(custom(function = "validate_address"))]
+    pub destination: String,
+    /// Amount of native token transferred, a 32 bytes big endian hex string. None transfers nothing
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    #[validate(length(equal = 66), regex(path = "*ETH_U256_REGEXP"))]
+    pub value: Option<String>,
+    pub payload: String,
+}
+
+/// Voucher executed as a delegate call of the destination, so it carries no value
+#[derive(Debug, Clone, Serialize, Deserialize, Validate)]
+pub struct DelegateCallVoucher {
+    #[validate(custom(function = "validate_address"))]
     pub destination: String,
-    #[validate(regex(path = "*ETH_U256_REGEXP"))]
-    pub value: String,
     pub payload: String,
 }
 
@@ -271,10 +367,10 @@ pub fn rollup_finish_request(
 
     if res < 0 {
         log::error!("failed to write finish request, IOCTL error {}", res);
-        return Err(Box::new(RollupError::new(&format!(
-            "IOCTL_ROLLUP_FINISH returned error {}",
-            res
-        ))));
+        return Err(Box::new(RollupError::from_device(
+            &format!("IOCTL_ROLLUP_FINISH returned error {}", res),
+            res,
+        )));
     }
 
     *finish = RollupFinish::from(*finish_c);
@@ -317,10 +413,10 @@ pub fn rollup_read_advance_state_request(
     let res = unsafe { cmt_rollup_read_advance_state(fd.0, advance_request.as_mut()) };
 
     if res != 0 {
-        return Err(Box::new(RollupError::new(&format!(
-            "IOCTL_ROLLUP_READ_ADVANCE_STATE returned error {}",
-            res
-        ))));
+        return Err(Box::new(RollupError::from_device(
+            &format!("IOCTL_ROLLUP_READ_ADVANCE_STATE returned error {}", res),
+            res,
+        )));
     }
 
     if advance_request.payload.length == 0 {
@@ -360,10 +456,10 @@ pub fn rollup_read_inspect_state_request(
     let res = unsafe { cmt_rollup_read_inspect_state(fd.0, inspect_request.as_mut()) };
 
     if res != 0 {
-        return Err(Box::new(RollupError::new(&format!(
-            "IOCTL_ROLLUP_READ_INSPECT_STATE returned error {}",
-            res
-        ))));
+        return Err(Box::new(RollupError::from_device(
+            &format!("IOCTL_ROLLUP_READ_INSPECT_STATE returned error {}", res),
+            res,
+        )));
     }
 
     if inspect_request.payload.length == 0 {
@@ -402,9 +498,9 @@ pub fn rollup_write_notice(
     let mut binary_payload = match hex::decode(&notice.payload[2..]) {
         Ok(payload) => payload,
         Err(_err) => {
-            return Err(Box::new(RollupError::new(&format!(
-                "Error decoding notice payload, payload must be in Ethereum hex binary format"
-            ))));
+            return Err(Box::new(RollupError::new(
+                "Error decoding notice payload, payload must be in Ethereum hex binary format",
+            )));
         }
     };
 
@@ -417,10 +513,10 @@ pub fn rollup_write_notice(
     let res = unsafe { cmt_rollup_emit_notice(fd.0, &payload, &mut notice_index) };
 
     if res != 0 {
-        return Err(Box::new(RollupError::new(&format!(
-            "IOCTL_ROLLUP_WRITE_NOTICE returned error {}",
-            res
-        ))));
+        return Err(Box::new(RollupError::from_device(
+            &format!("IOCTL_ROLLUP_WRITE_NOTICE returned error {}", res),
+            res,
+        )));
     } else {
         log::debug!("notice with id {} successfully written!", notice_index);
     }
@@ -437,12 +533,15 @@ pub fn rollup_write_voucher(
     let mut binary_payload = match hex::decode(&voucher.payload[2..]) {
         Ok(payload) => payload,
         Err(_err) => {
-            return Err(Box::new(RollupError::new(&format!(
-                "Error decoding voucher payload, it must be in Ethereum hex binary format"
-            ))));
+            return Err(Box::new(RollupError::new(
+                "Error decoding voucher payload, it must be in Ethereum hex binary format",
+            )));
         }
     };
-    let value = cmt_abi_u256_t::from_hex(&voucher.value[2..])?;
+    let value = match voucher.value.as_deref() {
+        Some(value) => cmt_abi_u256_t::from_hex(&value[2..])?,
+        None => unsafe { std::mem::zeroed() },
+    };
     let address = cmt_abi_address_t::from_hex(&voucher.destination[2..])?;
     let payload = cmt_abi_bytes_t {
         data: binary_payload.as_mut_ptr() as *mut c_void,
@@ -454,10 +553,10 @@ pub fn rollup_write_voucher(
         unsafe { cmt_rollup_emit_voucher(fd.0, &address, &value, &payload, &mut voucher_index) };
 
     if res != 0 {
-        return Err(Box::new(RollupError::new(&format!(
-            "IOCTL_ROLLUP_WRITE_VOUCHER returned error {}",
-            res
-        ))));
+        return Err(Box::new(RollupError::from_device(
+            &format!("IOCTL_ROLLUP_WRITE_VOUCHER returned error {}", res),
+            res,
+        )));
     } else {
         log::debug!("voucher with id {} successfully written!", voucher_index);
     }
@@ -465,6 +564,49 @@ pub fn rollup_write_voucher(
     Ok(voucher_index as u64)
 }
 
+pub fn rollup_write_delegate_call_voucher(
+    fd: &RollupFd,
+    voucher: &mut DelegateCallVoucher,
+) -> Result<u64, Box<dyn std::error::Error>> {
+    print_delegate_call_voucher(voucher);
+
+    let mut binary_payload = match hex::decode(&voucher.payload[2..]) {
+        Ok(payload) => payload,
+        Err(_err) => {
+            return Err(Box::new(RollupError::new(
+                "Error decoding delegate call voucher payload, it must be in Ethereum hex binary format",
+            )));
+        }
+    };
+    let address = cmt_abi_address_t::from_hex(&voucher.destination[2..])?;
+    let payload = cmt_abi_bytes_t {
+        data: binary_payload.as_mut_ptr() as *mut c_void,
+        length: binary_payload.len(),
+    };
+
+    let mut voucher_index: std::os::raw::c_ulong = 0;
+    let res = unsafe {
+        cmt_rollup_emit_delegate_call_voucher(fd.0, &address, &payload, &mut voucher_index)
+    };
+
+    if res != 0 {
+        return Err(Box::new(RollupError::from_device(
+            &format!(
+                "cmt_rollup_emit_delegate_call_voucher returned error {}",
+                res
+            ),
+            res,
+        )));
+    } else {
+        log::debug!(
+            "delegate call voucher with id {} successfully written!",
+            voucher_index
+        );
+    }
+
+    Ok(voucher_index as u64)
+}
+
 pub fn rollup_write_report(
     fd: &RollupFd,
     report: &Report,
@@ -474,9 +616,9 @@ pub fn rollup_write_report(
     let mut binary_payload = match hex::decode(&report.payload[2..]) {
         Ok(payload) => payload,
         Err(_err) => {
-            return Err(Box::new(RollupError::new(&format!(
-                "Error decoding report payload, payload must be in Ethereum hex binary format"
-            ))));
+            return Err(Box::new(RollupError::new(
+                "Error decoding report payload, payload must be in Ethereum hex binary format",
+            )));
         }
     };
 
@@ -488,10 +630,10 @@ pub fn rollup_write_report(
     let res = unsafe { cmt_rollup_emit_report(fd.0, &payload) };
 
     if res != 0 {
-        return Err(Box::new(RollupError::new(&format!(
-            "IOCTL_ROLLUP_WRITE_REPORT returned error {}",
-            res
-        ))));
+        return Err(Box::new(RollupError::from_device(
+            &format!("IOCTL_ROLLUP_WRITE_REPORT returned error {}", res),
+            res,
+        )));
     } else {
         log::debug!("report successfully written!");
     }
@@ -499,6 +641,70 @@ pub fn rollup_write_report(
     Ok(())
 }
 
+/// Size of the buffer the rollup device uses to transmit each output
+pub fn rollup_output_buffer_size(fd: &RollupFd) -> usize {
+    if fd.0.is_null() {
+        return 0;
+    }
+    let tx = unsafe { cmt_io_get_tx((*fd.0).io.as_mut_ptr()) };
+    tx.end as usize - tx.begin as usize
+}
+
+/// Size of the buffer the rollup device uses to receive requests
+pub fn rollup_input_buffer_size(fd: &RollupFd) -> usize {
+    if fd.0.is_null() {
+        return 0;
+    }
+    let rx = unsafe { cmt_io_get_rx((*fd.0).io.as_mut_ptr()) };
+    rx.end as usize - rx.begin as usize
+}
+
+/// Check the rollup device provides the buffers required to exchange requests and outputs
+pub fn rollup_probe_device(fd: &RollupFd) -> Result<(), RollupError> {
+    if rollup_input_buffer_size(fd) == 0 {
+        return Err(RollupError::new("rollup device has no input buffer"));
+    }
+    if rollup_output_buffer_size(fd) <= VOUCHER_ABI_OVERHEAD {
+        return Err(RollupError::new(
+            "rollup device output buffer is too small for vouchers",
+        ));
+    }
+    Ok(())
+}
+
+/// Open the rollup device, failing if it is not supported and `require_supported_device` is set
+pub fn open_rollup_device(require_supported_device: bool) -> std::io::Result<RollupFd> {
+    let fd = RollupFd::create()
+        .map_err(|e| std::io::Error::other(format!("unable to open rollup device, error {}", e)))?;
+    check_rollup_device(fd, require_supported_device)
+}
+
+/// Probe an opened rollup device, refusing it if it is not supported and
+/// `require_supported_device` is set
+pub fn check_rollup_device(
+    fd: RollupFd,
+    require_supported_device: bool,
+) -> std::io::Result<RollupFd> {
+    if let Err(e) = rollup_probe_device(&fd) {
+        if require_supported_device {
+            return Err(std::io::Error::new(
+                ErrorKind::Unsupported,
+                format!("unsupported rollup device: {}", e),
+            ));
+        }
+        log::warn!("rollup device may not be supported: {}", e);
+    }
+    Ok(fd)
+}
+
+/// Conservative estimate of the largest payload the next output can carry, assuming
+/// the overhead of a voucher and the padding of the payload to a word boundary
+pub fn rollup_output_payload_space(fd: &RollupFd) -> usize {
+    let word_size = CMT_ABI_U256_LENGTH as usize;
+    let available = rollup_output_buffer_size(fd).saturating_sub(VOUCHER_ABI_OVERHEAD);
+    available / word_size * word_size
+}
+
 pub fn gio_request(
     fd: &RollupFd,
     gio: &GIORequest,
@@ -506,9 +712,9 @@ pub fn gio_request(
     let binary_payload = match hex::decode(&gio.id[2..]) {
         Ok(payload) => payload,
         Err(_err) => {
-            return Err(Box::new(RollupError::new(&format!(
-                "Error decoding gio request payload, payload must be in Ethereum hex binary format"
-            ))));
+            return Err(Box::new(RollupError::new(
+                "Error decoding gio request payload, payload must be in Ethereum hex binary format",
+            )));
         }
     };
 
@@ -534,10 +740,10 @@ pub fn gio_request(
     let res = unsafe { cmt_gio_request(fd.0, gio_request.as_mut()) };
 
     if res != 0 {
-        return Err(Box::new(RollupError::new(&format!(
-            "GIO request returned error {}",
-            res
-        ))));
+        return Err(Box::new(RollupError::from_device(
+            &format!("GIO request returned error {}", res),
+            res,
+        )));
     }
 
     let mut gio_response: Vec<u8> = Vec::with_capacity(gio_request.response_data_length as usize);
@@ -572,9 +778,9 @@ pub fn rollup_throw_exception(
     let mut binary_payload = match hex::decode(&exception.payload[2..]) {
         Ok(payload) => payload,
         Err(_err) => {
-            return Err(Box::new(RollupError::new(&format!(
-                "Error decoding report payload, payload must be in Ethereum hex binary format"
-            ))));
+            return Err(Box::new(RollupError::new(
+                "Error decoding report payload, payload must be in Ethereum hex binary format",
+            )));
         }
     };
 
@@ -585,10 +791,10 @@ pub fn rollup_throw_exception(
 
     let res = unsafe { cmt_rollup_emit_exception(fd.0, &payload) };
     if res != 0 {
-        return Err(Box::new(RollupError::new(&format!(
-            "IOCTL_ROLLUP_THROW_EXCEPTION returned error {}",
-            res
-        ))));
+        return Err(Box::new(RollupError::from_device(
+            &format!("IOCTL_ROLLUP_THROW_EXCEPTION returned error {}", res),
+            res,
+        )));
     } else {
         log::debug!("exception successfully thrown!");
     }
@@ -599,8 +805,10 @@ pub async fn perform_rollup_finish_request(
     fd: &RollupFd,
     accept: bool,
 ) -> std::io::Result<RollupFinish> {
-    let mut finish_request = RollupFinish::default();
-    finish_request.accept_previous_request = accept;
+    let mut finish_request = RollupFinish {
+        accept_previous_request: accept,
+        ..Default::default()
+    };
 
     match rollup_finish_request(fd, &mut finish_request) {
         Ok(_) => {
@@ -609,7 +817,7 @@ pub async fn perform_rollup_finish_request(
         }
         Err(e) => {
             log::error!("error inserting finish request, details: {}", e.to_string());
-            Err(std::io::Error::new(ErrorKind::Other, e.to_string()))
+            Err(std::io::Error::other(e.to_string()))
         }
     }
 }
@@ -629,7 +837,7 @@ pub async fn handle_rollup_requests(
                 match rollup_read_advance_state_request(fd) {
                     Ok(r) => r,
                     Err(e) => {
-                        return Err(std::io::Error::new(ErrorKind::Other, e.to_string()));
+                        return Err(std::io::Error::other(e.to_string()));
                     }
                 }
             };
@@ -646,7 +854,7 @@ pub async fn handle_rollup_requests(
                 match rollup_read_inspect_state_request(fd) {
                     Ok(r) => r,
                     Err(e) => {
-                        return Err(std::io::Error::new(ErrorKind::Other, e.to_string()));
+                        return Err(std::io::Error::other(e.to_string()));
                     }
                 }
             };
@@ -656,12 +864,10 @@ pub async fn handle_rollup_requests(
             // Send newly read inspect request to http service
             Ok(RollupRequest::Inspect(inspect_request))
         }
-        _ => {
-            return Err(std::io::Error::new(
-                ErrorKind::Unsupported,
-                "request type unsupported",
-            ));
-        }
+        _ => Err(std::io::Error::new(
+            ErrorKind::Unsupported,
+            "request type unsupported",
+        )),
     }
 }
 
@@ -714,6 +920,18 @@ pub fn print_voucher(voucher: &Voucher) {
     log::debug!("{}", &voucher_request_printout);
 }
 
+pub fn print_delegate_call_voucher(voucher: &DelegateCallVoucher) {
+    let mut voucher_request_printout = String::new();
+    voucher_request_printout.push_str("delegate call voucher: { destination: ");
+    format_address_printout(&voucher.destination, &mut voucher_request_printout);
+    voucher_request_printout.push_str(&format!(
+        " length: {} payload: {} }}",
+        voucher.payload.len(),
+        voucher.payload
+    ));
+    log::debug!("{}", &voucher_request_printout);
+}
+
 pub fn print_report(report: &Report) {
     log::debug!(
         "report: {{ length: {} payload: {}}}",
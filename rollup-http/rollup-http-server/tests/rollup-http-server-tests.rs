@@ -24,7 +24,7 @@ use rollup_http_client::rollup::{
     Exception, GIORequest, Notice, Report, RollupRequest, RollupResponse, Voucher,
 };
 use rollup_http_server::config::Config;
-use rollup_http_server::rollup::RollupFd;
+use rollup_http_server::rollup::{self, RollupFd};
 use rollup_http_server::*;
 use rstest::*;
 use std::env;
@@ -48,18 +48,57 @@ impl Drop for Context {
     }
 }
 
+/*
+ * cast calldata "EvmAdvance(uint256,address,address,uint256,uint256,uint256,uint256,bytes)" \
+ *     0x0000000000000000000000000000000000000001 \
+ *     0x0000000000000000000000000000000000000002 \
+ *     0x0000000000000000000000000000000000000003 \
+ *     0x0000000000000000000000000000000000000004 \
+ *     0x0000000000000000000000000000000000000005 \
+ *     0x0000000000000000000000000000000000000006 \
+ *     0x0000000000000000000000000000000000000007 \
+ *     0x`echo -e "advance-0" | xxd -p -c0`
+ */
+const ADVANCE_PAYLOAD_FIELD: &str = "advance-0\n"; // must match `cast` invocation!
+const ADVANCE_PAYLOAD_DATA: &str = "415bf363\
+                                    0000000000000000000000000000000000000000000000000000000000000001\
+                                    0000000000000000000000000000000000000000000000000000000000000002\
+                                    0000000000000000000000000000000000000000000000000000000000000003\
+                                    0000000000000000000000000000000000000000000000000000000000000004\
+                                    0000000000000000000000000000000000000000000000000000000000000005\
+                                    0000000000000000000000000000000000000000000000000000000000000006\
+                                    0000000000000000000000000000000000000000000000000000000000000007\
+                                    0000000000000000000000000000000000000000000000000000000000000100\
+                                    000000000000000000000000000000000000000000000000000000000000000a\
+                                    616476616e63652d300a00000000000000000000000000000000000000000000";
+
+fn create_rollup_fd() -> RollupFd {
+    // The mock device can only be opened once, wait for the previous test server to release it
+    let mut count = 50;
+    loop {
+        match RollupFd::create() {
+            Ok(rollup_fd) => return rollup_fd,
+            Err(e) if count > 0 => {
+                println!("Rollup device not available ({}), retrying", e);
+                std::thread::sleep(std::time::Duration::from_millis(100));
+            }
+            Err(e) => panic!("unable to open rollup device: {}", e),
+        }
+        count -= 1;
+    }
+}
+
 fn run_test_http_service(
-    host: &str,
-    port: u16,
+    http_config: &Config,
 ) -> std::io::Result<Option<actix_server::ServerHandle>> {
-    let rollup_fd: Arc<Mutex<RollupFd>> = Arc::new(Mutex::new(RollupFd::create().unwrap()));
-    let rollup_fd = rollup_fd.clone();
-    let http_config = Config {
-        http_address: host.to_string(),
-        http_port: port,
+    // As the server does, do not open the device in the dry run mode
+    let rollup_fd = match http_config.dry_run {
+        true => RollupFd::detached(),
+        false => create_rollup_fd(),
     };
+    let rollup_fd: Arc<Mutex<RollupFd>> = Arc::new(Mutex::new(rollup_fd));
     println!("Creating http server");
-    let server = http_service::create_server(&http_config, rollup_fd)?;
+    let server = http_service::create_server(http_config, rollup_fd)?;
     let server_handle = server.handle();
     println!("Spawning http server");
     tokio::spawn(server);
@@ -67,21 +106,22 @@ fn run_test_http_service(
     Ok(Some(server_handle))
 }
 
-#[fixture]
-async fn context_future() -> Context {
+async fn create_context(mut http_config: Config) -> Context {
     let mut server_handle: Option<ServerHandle> = None;
     let mut count = 5;
     let mut port;
     loop {
         port = rand::thread_rng().gen_range(49152..65535);
+        http_config.http_address = HOST.to_string();
+        http_config.http_port = port;
 
-        match run_test_http_service(HOST, port) {
+        match run_test_http_service(&http_config) {
             Ok(handle) => {
                 server_handle = handle;
                 break;
             }
             Err(ex) => {
-                eprint!("Error instantiating rollup http service {}", ex.to_string());
+                eprint!("Error instantiating rollup http service {}", ex);
                 if count > 0 {
                     // wait for the system to free port
                     std::thread::sleep(std::time::Duration::from_secs(1));
@@ -90,7 +130,7 @@ async fn context_future() -> Context {
                 }
             }
         };
-        count = count - 1;
+        count -= 1;
     }
 
     Context {
@@ -99,6 +139,74 @@ async fn context_future() -> Context {
     }
 }
 
+#[fixture]
+async fn context_future(#[default(Config::new())] config: Config) -> Context {
+    create_context(config).await
+}
+
+async fn get_request(address: &str, path: &str) -> hyper::Response<hyper::Body> {
+    let client = hyper::Client::new();
+    let req = hyper::Request::builder()
+        .method(hyper::Method::GET)
+        .uri(address.to_string() + path)
+        .body(hyper::Body::empty())
+        .expect("http request");
+    client.request(req).await.expect("http response")
+}
+
+async fn post_request(address: &str, path: &str, body: String) -> hyper::Response<hyper::Body> {
+    let client = hyper::Client::new();
+    let req = hyper::Request::builder()
+        .method(hyper::Method::POST)
+        .header(hyper::header::CONTENT_TYPE, "application/json")
+        .uri(address.to_string() + path)
+        .body(hyper::Body::from(body))
+        .expect("http request");
+    client.request(req).await.expect("http response")
+}
+
+/// Description of the json error a failed request is answered with
+async fn error_description(
+    response: hyper::Response<hyper::Body>,
+) -> Result<String, Box<dyn std::error::Error>> {
+    let body = hyper::body::to_bytes(response).await?;
+    let error: serde_json::Value = serde_json::from_slice(&body)?;
+    Ok(error["error"]["description"]
+        .as_str()
+        .unwrap_or_default()
+        .to_string())
+}
+
+fn write_advance_input(path: &str) -> std::io::Result<()> {
+    let mut advance_file = File::create(path)?;
+    advance_file.write_all(&hex::decode(ADVANCE_PAYLOAD_DATA).unwrap())
+}
+
+/// Same metadata as `ADVANCE_PAYLOAD_DATA`, with an arbitrary payload
+fn write_advance_input_with_payload(path: &str, payload: &[u8]) -> std::io::Result<()> {
+    write_advance_input_at_block(path, 4, payload)
+}
+
+/// Same metadata as `ADVANCE_PAYLOAD_DATA`, with an arbitrary block number and payload
+fn write_advance_input_at_block(
+    path: &str,
+    block_number: u64,
+    payload: &[u8],
+) -> std::io::Result<()> {
+    let mut advance_data = hex::decode("415bf363").unwrap();
+    advance_data.extend(ethabi::encode(&[
+        ethabi::Token::Uint(1.into()),
+        ethabi::Token::Address(ethabi::Address::from_low_u64_be(2)),
+        ethabi::Token::Address(ethabi::Address::from_low_u64_be(3)),
+        ethabi::Token::Uint(block_number.into()),
+        ethabi::Token::Uint(5.into()),
+        ethabi::Token::Uint(6.into()),
+        ethabi::Token::Uint(7.into()),
+        ethabi::Token::Bytes(payload.to_vec()),
+    ]));
+    std::fs::write(path, advance_data)
+}
+
 #[rstest]
 #[tokio::test]
 async fn test_server_instance_creation(
@@ -119,44 +227,18 @@ async fn test_server_instance_creation(
 async fn test_finish_request(
     context_future: impl Future<Output = Context>,
 ) -> Result<(), Box<dyn std::error::Error>> {
-    /*
-     * cast calldata "EvmAdvance(uint256,address,address,uint256,uint256,uint256,uint256,bytes)" \
-     *     0x0000000000000000000000000000000000000001 \
-     *     0x0000000000000000000000000000000000000002 \
-     *     0x0000000000000000000000000000000000000003 \
-     *     0x0000000000000000000000000000000000000004 \
-     *     0x0000000000000000000000000000000000000005 \
-     *     0x0000000000000000000000000000000000000006 \
-     *     0x0000000000000000000000000000000000000007 \
-     *     0x`echo -e "advance-0" | xxd -p -c0`
-     */
-    let advance_payload_field = "advance-0\n"; // must match `cast` invocation!
-    let advance_payload_data = "415bf363\
-                                0000000000000000000000000000000000000000000000000000000000000001\
-                                0000000000000000000000000000000000000000000000000000000000000002\
-                                0000000000000000000000000000000000000000000000000000000000000003\
-                                0000000000000000000000000000000000000000000000000000000000000004\
-                                0000000000000000000000000000000000000000000000000000000000000005\
-                                0000000000000000000000000000000000000000000000000000000000000006\
-                                0000000000000000000000000000000000000000000000000000000000000007\
-                                0000000000000000000000000000000000000000000000000000000000000100\
-                                000000000000000000000000000000000000000000000000000000000000000a\
-                                616476616e63652d300a00000000000000000000000000000000000000000000";
-
     /*
      * inspect requests are not evm encoded
      */
     let inspect_payload_data = "inspect-0";
 
-    let advance_binary_data = hex::decode(advance_payload_data).unwrap();
     let advance_path = "advance_payload.bin";
-    let mut advance_file = File::create(advance_path)?;
-    advance_file.write_all(&advance_binary_data)?;
+    write_advance_input(advance_path)?;
 
     let inspect_binary_data = inspect_payload_data.as_bytes();
     let inspect_path = "inspect_payload.bin";
     let mut inspect_file = File::create(inspect_path)?;
-    inspect_file.write_all(&inspect_binary_data)?;
+    inspect_file.write_all(inspect_binary_data)?;
 
     env::set_var(
         "CMT_INPUTS",
@@ -184,7 +266,7 @@ async fn test_finish_request(
 
                 let payload_bytes = hex::decode(&advance_request.payload[2..]).unwrap();
                 let payload_string = String::from_utf8(payload_bytes).unwrap();
-                assert_eq!(payload_string, advance_payload_field);
+                assert_eq!(payload_string, ADVANCE_PAYLOAD_FIELD);
             }
         },
         Err(err) => {
@@ -241,7 +323,7 @@ fn check_voucher_or_fail(original_voucher: Voucher, output_filename: &str) {
         original_voucher.destination,
     );
     assert_eq!(
-        "0x".to_string() + &decoded_voucher[1].to_string(),
+        format!("0x{:064x}", decoded_voucher[1].clone().into_uint().unwrap()),
         original_voucher.value,
     );
     assert_eq!(
@@ -259,12 +341,12 @@ async fn test_write_voucher(
     println!("Writing voucher");
     let test_voucher_01 = Voucher {
         destination: "0x1111111111111111111111111111111111111111".to_string(),
-        value: "0xdeadbeef".to_string(),
+        value: "0x00000000000000000000000000000000000000000000000000000000deadbeef".to_string(),
         payload: "0x".to_string() + &hex::encode("voucher test payload 01"),
     };
     let test_voucher_02 = Voucher {
         destination: "0x2222222222222222222222222222222222222222".to_string(),
-        value: "0xdeadbeef".to_string(),
+        value: "0x00000000000000000000000000000000000000000000000000000000deadbeef".to_string(),
         payload: "0x".to_string() + &hex::encode("voucher test payload 02"),
     };
     rollup_http_client::client::send_voucher(&context.address, test_voucher_01.clone()).await;
@@ -385,3 +467,2998 @@ async fn test_exception_throw(
     std::fs::remove_file("none.exception-0.bin")?;
     Ok(())
 }
+
+#[rstest]
+#[tokio::test]
+async fn test_max_pending_outputs(
+    #[with(Config {
+        max_pending_outputs: Some(2),
+        ..Config::new()
+    })]
+    context_future: impl Future<Output = Context>,
+) -> Result<(), Box<dyn std::error::Error>> {
+    let advance_path = "max_outputs_advance.bin";
+    write_advance_input(advance_path)?;
+    env::set_var("CMT_INPUTS", format!("0:{}", advance_path));
+
+    let context = context_future.await;
+    let notice = serde_json::to_string(&Notice {
+        payload: "0x".to_string() + &hex::encode("pending output"),
+    })?;
+    let report = serde_json::to_string(&Report {
+        payload: "0x".to_string() + &hex::encode("pending output"),
+    })?;
+    let notice_status = post_request(&context.address, "/notice", notice.clone())
+        .await
+        .status();
+    let report_status = post_request(&context.address, "/report", report)
+        .await
+        .status();
+    let over_limit_status = post_request(&context.address, "/notice", notice.clone())
+        .await
+        .status();
+
+    // Finishing the request resets the counter
+    let request = rollup_http_client::client::send_finish_request(
+        &context.address,
+        &RollupResponse::Finish(true),
+    )
+    .await;
+    let after_finish_status = post_request(&context.address, "/notice", notice)
+        .await
+        .status();
+    context.server_handle.stop(true).await;
+
+    assert_eq!(notice_status, hyper::StatusCode::CREATED);
+    assert_eq!(report_status, hyper::StatusCode::ACCEPTED);
+    assert_eq!(over_limit_status, hyper::StatusCode::TOO_MANY_REQUESTS);
+    assert!(matches!(request, Ok(RollupRequest::Advance(_))));
+    assert_eq!(after_finish_status, hyper::StatusCode::CREATED);
+
+    std::fs::remove_file("none.output-0.bin")?;
+    std::fs::remove_file("none.report-0.bin")?;
+    std::fs::remove_file("max_outputs_advance.output-0.bin")?;
+    std::fs::remove_file(advance_path)?;
+    Ok(())
+}
+
+#[rstest]
+#[tokio::test]
+async fn test_finish_timeout(
+    #[with(Config {
+        finish_timeout_ms: Some(200),
+        ..Config::new()
+    })]
+    context_future: impl Future<Output = Context>,
+) -> Result<(), Box<dyn std::error::Error>> {
+    // Reading the next input from a fifo blocks the finish until it is written to
+    let fifo_path = "finish_timeout.fifo";
+    let advance_path = "finish_timeout_advance.bin";
+    assert!(std::process::Command::new("mkfifo")
+        .arg(fifo_path)
+        .status()?
+        .success());
+    write_advance_input(advance_path)?;
+    env::set_var("CMT_INPUTS", format!("0:{},0:{}", fifo_path, advance_path));
+
+    let context = context_future.await;
+    let finish = "{\"status\":\"accept\"}".to_string();
+    let timed_out = post_request(&context.address, "/finish", finish.clone()).await;
+    let timed_out_status = timed_out.status();
+    let timed_out_description = error_description(timed_out).await?;
+    let writer = std::thread::spawn(move || std::fs::write(fifo_path, b"fifo input"));
+    // The next finish gets the outcome of the stuck one instead of finishing again.
+    // The mock device cannot seek in a fifo, so it fails reading it
+    let stuck_status = post_request(&context.address, "/finish", finish.clone())
+        .await
+        .status();
+    let next_status = post_request(&context.address, "/finish", finish)
+        .await
+        .status();
+    context.server_handle.stop(true).await;
+    writer.join().unwrap()?;
+
+    assert_eq!(timed_out_status, hyper::StatusCode::GATEWAY_TIMEOUT);
+    assert_eq!(
+        timed_out_description,
+        "no next request from the rollup device after 200 ms, finish again to keep waiting"
+    );
+    assert_eq!(stuck_status, hyper::StatusCode::SERVICE_UNAVAILABLE);
+    assert_eq!(next_status, hyper::StatusCode::OK);
+
+    std::fs::remove_file(fifo_path)?;
+    std::fs::remove_file(advance_path)?;
+    std::fs::remove_file("none.outputs_root_hash.bin")?;
+    Ok(())
+}
+
+#[rstest]
+#[tokio::test]
+async fn test_finish_releases_context(
+    context_future: impl Future<Output = Context>,
+) -> Result<(), Box<dyn std::error::Error>> {
+    // Reading the next input from a fifo blocks the finish until it is written to
+    let fifo_path = "finish_releases_context.fifo";
+    assert!(std::process::Command::new("mkfifo")
+        .arg(fifo_path)
+        .status()?
+        .success());
+    env::set_var("CMT_INPUTS", format!("0:{}", fifo_path));
+
+    let context = context_future.await;
+    let address = context.address.clone();
+    let finish = tokio::spawn(async move {
+        post_request(&address, "/finish", "{\"status\":\"accept\"}".to_string())
+            .await
+            .status()
+    });
+    // Let the finish reach the rollup device
+    tokio::time::sleep(std::time::Duration::from_millis(200)).await;
+    let waiting = std::time::Duration::from_secs(2);
+    let time_status = tokio::time::timeout(waiting, get_request(&context.address, "/time"))
+        .await?
+        .status();
+    let config_status = tokio::time::timeout(waiting, get_request(&context.address, "/config"))
+        .await?
+        .status();
+    let notice = serde_json::json!({ "payload": "0x01" }).to_string();
+    let notice_response =
+        tokio::time::timeout(waiting, post_request(&context.address, "/notice", notice)).await?;
+    let notice_status = notice_response.status();
+    let notice_description = error_description(notice_response).await?;
+    let second_finish_status = tokio::time::timeout(
+        waiting,
+        post_request(
+            &context.address,
+            "/finish",
+            "{\"status\":\"accept\"}".to_string(),
+        ),
+    )
+    .await?
+    .status();
+    std::fs::write(fifo_path, b"fifo input")?;
+    // The mock device cannot seek in a fifo, so it fails reading it
+    let finish_status = finish.await?;
+    context.server_handle.stop(true).await;
+
+    assert_eq!(time_status, hyper::StatusCode::OK);
+    assert_eq!(config_status, hyper::StatusCode::OK);
+    assert_eq!(notice_status, hyper::StatusCode::SERVICE_UNAVAILABLE);
+    assert_eq!(
+        notice_description,
+        "a finish request is waiting for the next request on the rollup device"
+    );
+    assert_eq!(second_finish_status, hyper::StatusCode::CONFLICT);
+    assert_eq!(finish_status, hyper::StatusCode::SERVICE_UNAVAILABLE);
+
+    std::fs::remove_file(fifo_path)?;
+    Ok(())
+}
+
+#[rstest]
+#[tokio::test]
+async fn test_dry_run(
+    #[with(Config {
+        dry_run: true,
+        ..Config::new()
+    })]
+    context_future: impl Future<Output = Context>,
+) -> Result<(), Box<dyn std::error::Error>> {
+    // Without inputs, a finish reaching the mock device would fail
+    env::set_var("CMT_INPUTS", "");
+    let context = context_future.await;
+    let mut responses = Vec::new();
+    for (path, body) in [
+        (
+            "/voucher",
+            serde_json::json!({
+                "destination": "0x1111111111111111111111111111111111111111",
+                "value": "0x00000000000000000000000000000000000000000000000000000000deadbeef",
+                "payload": "0x00",
+            }),
+        ),
+        ("/notice", serde_json::json!({ "payload": "0x01" })),
+        ("/report", serde_json::json!({ "payload": "0x02" })),
+        ("/gio", serde_json::json!({ "domain": 0x10, "id": "0x03" })),
+        ("/finish", serde_json::json!({ "status": "accept" })),
+        // Indices keep increasing across requests
+        ("/notice", serde_json::json!({ "payload": "0x04" })),
+    ] {
+        let response = post_request(&context.address, path, body.to_string()).await;
+        let status = response.status();
+        let body = hyper::body::to_bytes(response).await?;
+        responses.push((status, String::from_utf8(body.to_vec())?));
+    }
+    context.server_handle.stop(true).await;
+
+    assert_eq!(
+        responses,
+        [
+            (hyper::StatusCode::CREATED, "{\"index\":0}".to_string()),
+            (hyper::StatusCode::CREATED, "{\"index\":1}".to_string()),
+            (hyper::StatusCode::ACCEPTED, String::new()),
+            (
+                hyper::StatusCode::ACCEPTED,
+                "{\"response\":\"0x\",\"response_code\":0}".to_string()
+            ),
+            (
+                hyper::StatusCode::OK,
+                "{\"request_type\":\"inspect_state\",\"data\":{\"payload\":\"0x\"}}".to_string()
+            ),
+            (hyper::StatusCode::CREATED, "{\"index\":2}".to_string()),
+        ]
+    );
+    for output in ["none.output-0.bin", "none.report-0.bin", "none.gio-0.bin"] {
+        assert!(!std::path::Path::new(output).exists(), "{} written", output);
+    }
+    Ok(())
+}
+
+#[rstest]
+#[tokio::test]
+async fn test_dry_run_finish_interval(
+    #[with(Config {
+        dry_run: true,
+        dry_run_finish_interval_ms: 300,
+        ..Config::new()
+    })]
+    context_future: impl Future<Output = Context>,
+) -> Result<(), Box<dyn std::error::Error>> {
+    let context = context_future.await;
+    let mut durations = Vec::new();
+    for _ in 0..3 {
+        let started = std::time::Instant::now();
+        let status = post_request(
+            &context.address,
+            "/finish",
+            "{\"status\":\"accept\"}".to_string(),
+        )
+        .await
+        .status();
+        assert_eq!(status, hyper::StatusCode::OK);
+        durations.push(started.elapsed());
+    }
+    context.server_handle.stop(true).await;
+
+    // A DApp looping on finish gets the synthetic inspects spaced, instead of spinning
+    for duration in durations {
+        assert!(
+            duration >= std::time::Duration::from_millis(300),
+            "finish answered after {:?}",
+            duration
+        );
+    }
+    Ok(())
+}
+
+#[rstest]
+#[tokio::test]
+async fn test_server_config(
+    #[with(Config {
+        max_notice_size: Some(1024),
+        max_pending_outputs: Some(8),
+        admin_token: Some("secret".to_string()),
+        ..Config::new()
+    })]
+    context_future: impl Future<Output = Context>,
+) -> Result<(), Box<dyn std::error::Error>> {
+    let context = context_future.await;
+    let response = get_request(&context.address, "/config").await;
+    let status = response.status();
+    let body = hyper::body::to_bytes(response).await?;
+    context.server_handle.stop(true).await;
+
+    assert_eq!(status, hyper::StatusCode::OK);
+    assert!(!String::from_utf8(body.to_vec())?.contains("secret"));
+    let server_config: serde_json::Value = serde_json::from_slice(&body)?;
+    assert_eq!(
+        server_config,
+        serde_json::json!({
+            "version": env!("CARGO_PKG_VERSION"),
+            "address_size": 20,
+            "max_json_body_size": 32768,
+            "max_notice_size": 1024,
+            "max_report_size": 32768,
+            "max_advance_payload_bytes": null,
+            "max_pending_outputs": 8,
+            "payload_download_threshold": null,
+        })
+    );
+    Ok(())
+}
+
+#[rstest]
+#[tokio::test]
+async fn test_delegate_call_voucher(
+    context_future: impl Future<Output = Context>,
+) -> Result<(), Box<dyn std::error::Error>> {
+    let context = context_future.await;
+    let destination = "0x1111111111111111111111111111111111111111";
+    let response = post_request(
+        &context.address,
+        "/delegate_call_voucher",
+        serde_json::json!({ "destination": destination, "payload": "0xdeadbeef" }).to_string(),
+    )
+    .await;
+    let status = response.status();
+    let body = hyper::body::to_bytes(response).await?;
+    let invalid_status = post_request(
+        &context.address,
+        "/delegate_call_voucher",
+        serde_json::json!({ "destination": "0x1111", "payload": "0x00" }).to_string(),
+    )
+    .await
+    .status();
+    context.server_handle.stop(true).await;
+
+    assert_eq!(status, hyper::StatusCode::CREATED);
+    assert_eq!(String::from_utf8(body.to_vec())?, "{\"index\":0}");
+    assert_eq!(invalid_status, hyper::StatusCode::BAD_REQUEST);
+    let output = std::fs::read("none.output-0.bin")?;
+    std::fs::remove_file("none.output-0.bin")?;
+    assert_eq!(
+        output[..4],
+        hash::keccak256(b"DelegateCallVoucher(address,bytes)")[..4]
+    );
+    assert_eq!(
+        ethabi::decode(
+            &[ethabi::ParamType::Address, ethabi::ParamType::Bytes],
+            &output[4..]
+        )?,
+        [
+            ethabi::Token::Address(destination.parse()?),
+            ethabi::Token::Bytes(vec![0xde, 0xad, 0xbe, 0xef]),
+        ]
+    );
+    Ok(())
+}
+
+#[rstest]
+#[tokio::test]
+async fn test_max_json_body_size(
+    #[with(Config {
+        max_json_body_size: 1024,
+        ..Config::new()
+    })]
+    context_future: impl Future<Output = Context>,
+) -> Result<(), Box<dyn std::error::Error>> {
+    // The mock device answers the gio request with the next input
+    let response_path = "json_limit_gio_response.bin";
+    std::fs::write(response_path, "gio response")?;
+    env::set_var("CMT_INPUTS", format!("0:{}", response_path));
+    let context = context_future.await;
+    let voucher = serde_json::json!({
+        "destination": "0x1111111111111111111111111111111111111111",
+        "payload": format!("0x{}", "00".repeat(1024)),
+    });
+    let voucher_status = post_request(&context.address, "/voucher", voucher.to_string())
+        .await
+        .status();
+    // Binary bodies are not bound by the json limit
+    let client = hyper::Client::new();
+    let request = hyper::Request::builder()
+        .method(hyper::Method::POST)
+        .uri(context.address.clone() + "/gio_binary")
+        .header("X-Gio-Domain", "0x10")
+        .body(hyper::Body::from(vec![0u8; 4096]))?;
+    let gio_status = client.request(request).await?.status();
+    context.server_handle.stop(true).await;
+
+    assert_eq!(voucher_status, hyper::StatusCode::PAYLOAD_TOO_LARGE);
+    assert_eq!(gio_status, hyper::StatusCode::ACCEPTED);
+    std::fs::remove_file("none.gio-0.bin")?;
+    std::fs::remove_file(response_path)?;
+    Ok(())
+}
+
+#[rstest]
+#[tokio::test]
+async fn test_ready_file(
+    #[with(Config {
+        ready_file: Some("rollup-http-server.ready".to_string()),
+        ..Config::new()
+    })]
+    context_future: impl Future<Output = Context>,
+) -> Result<(), Box<dyn std::error::Error>> {
+    let advance_path = "ready_file_advance.bin";
+    let ready_path = "rollup-http-server.ready";
+    write_advance_input(advance_path)?;
+    env::set_var("CMT_INPUTS", format!("0:{}", advance_path));
+
+    let context = context_future.await;
+    let created_before_finish = std::path::Path::new(ready_path).exists();
+    let request = rollup_http_client::client::send_finish_request(
+        &context.address,
+        &RollupResponse::Finish(true),
+    )
+    .await;
+    let created_after_finish = std::path::Path::new(ready_path).exists();
+    context.server_handle.stop(true).await;
+
+    // Workers release the context asynchronously after the server stops
+    let mut count = 50;
+    while std::path::Path::new(ready_path).exists() && count > 0 {
+        std::thread::sleep(std::time::Duration::from_millis(100));
+        count -= 1;
+    }
+
+    assert!(matches!(request, Ok(RollupRequest::Advance(_))));
+    assert!(!created_before_finish);
+    assert!(created_after_finish);
+    assert!(!std::path::Path::new(ready_path).exists());
+
+    std::fs::remove_file(advance_path)?;
+    Ok(())
+}
+
+#[rstest]
+#[tokio::test]
+async fn test_output_space(
+    context_future: impl Future<Output = Context>,
+) -> Result<(), Box<dyn std::error::Error>> {
+    let context = context_future.await;
+    let response = get_request(&context.address, "/output_space").await;
+    let status = response.status();
+    let body = hyper::body::to_bytes(response).await?;
+    context.server_handle.stop(true).await;
+
+    // the mock device transmits outputs through a 2MB buffer, the voucher header takes
+    // the selector plus four words and the payload is rounded down to a whole word
+    let output_space: serde_json::Value = serde_json::from_slice(&body)?;
+    assert_eq!(status, hyper::StatusCode::OK);
+    assert_eq!(output_space["buffer_size"], 2 << 20);
+    assert_eq!(output_space["payload_space"], (2 << 20) - 4 * 32 - 32);
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_require_supported_device() -> Result<(), Box<dyn std::error::Error>> {
+    // wait for the previous test server to release the mock device
+    let mut count = 50;
+    let rollup_fd = loop {
+        match rollup::open_rollup_device(true) {
+            Ok(rollup_fd) => break rollup_fd,
+            Err(_) if count > 0 => std::thread::sleep(std::time::Duration::from_millis(100)),
+            Err(e) => return Err(e.into()),
+        }
+        count -= 1;
+    };
+    assert!(rollup::rollup_probe_device(&rollup_fd).is_ok());
+
+    // the mock device is exclusive, a second instance is not usable
+    let error = rollup::open_rollup_device(true).err().unwrap();
+    assert!(error.to_string().contains("unable to open rollup device"));
+    drop(rollup_fd);
+
+    // a device without buffers fails the probe, refused only when a supported one is required
+    let error = rollup::check_rollup_device(RollupFd::detached(), true)
+        .err()
+        .unwrap();
+    assert_eq!(error.kind(), std::io::ErrorKind::Unsupported);
+    assert_eq!(
+        error.to_string(),
+        "unsupported rollup device: rollup error: rollup device has no input buffer"
+    );
+    assert!(rollup::check_rollup_device(RollupFd::detached(), false).is_ok());
+    Ok(())
+}
+
+#[rstest]
+#[tokio::test]
+async fn test_finish_streams_large_payload(
+    context_future: impl Future<Output = Context>,
+) -> Result<(), Box<dyn std::error::Error>> {
+    let advance_path = "large_advance.bin";
+    let payload: Vec<u8> = (0..256 * 1024).map(|i| (i % 251) as u8).collect();
+    write_advance_input_with_payload(advance_path, &payload)?;
+    env::set_var("CMT_INPUTS", format!("0:{}", advance_path));
+
+    let context = context_future.await;
+    let response = post_request(
+        &context.address,
+        "/finish",
+        "{\"status\":\"accept\"}".to_string(),
+    )
+    .await;
+    let status = response.status();
+    let transfer_encoding = response
+        .headers()
+        .get(hyper::header::TRANSFER_ENCODING)
+        .cloned();
+    let body = hyper::body::to_bytes(response).await?;
+    context.server_handle.stop(true).await;
+
+    let request: serde_json::Value = serde_json::from_slice(&body)?;
+    assert_eq!(status, hyper::StatusCode::OK);
+    assert_eq!(transfer_encoding.unwrap(), "chunked");
+    assert_eq!(request["request_type"], "advance_state");
+    assert_eq!(request["data"]["metadata"]["input_index"], 7);
+    assert_eq!(
+        request["data"]["payload"],
+        format!("0x{}", hex::encode(&payload))
+    );
+
+    std::fs::remove_file(advance_path)?;
+    Ok(())
+}
+
+#[rstest]
+#[tokio::test]
+async fn test_finish_latencies(
+    #[with(Config {
+        debug_endpoints: true,
+        ..Config::new()
+    })]
+    context_future: impl Future<Output = Context>,
+) -> Result<(), Box<dyn std::error::Error>> {
+    let advance_path = "latencies_advance.bin";
+    write_advance_input(advance_path)?;
+    env::set_var("CMT_INPUTS", format!("0:{0},0:{0},0:{0}", advance_path));
+
+    let context = context_future.await;
+    for _ in 0..3 {
+        let request = rollup_http_client::client::send_finish_request(
+            &context.address,
+            &RollupResponse::Finish(true),
+        )
+        .await;
+        assert!(matches!(request, Ok(RollupRequest::Advance(_))));
+    }
+    let response = get_request(&context.address, "/debug/finish_latencies").await;
+    let status = response.status();
+    let body = hyper::body::to_bytes(response).await?;
+    context.server_handle.stop(true).await;
+
+    let latencies: Vec<u64> = serde_json::from_slice(&body)?;
+    assert_eq!(status, hyper::StatusCode::OK);
+    assert_eq!(latencies.len(), 3);
+
+    std::fs::remove_file("latencies_advance.outputs_root_hash.bin")?;
+    std::fs::remove_file(advance_path)?;
+    Ok(())
+}
+
+#[rstest]
+#[tokio::test]
+async fn test_debug_memory(
+    #[with(Config {
+        debug_endpoints: true,
+        ..Config::new()
+    })]
+    context_future: impl Future<Output = Context>,
+) -> Result<(), Box<dyn std::error::Error>> {
+    let context = context_future.await;
+    let response = get_request(&context.address, "/debug/memory").await;
+    let status = response.status();
+    let body = hyper::body::to_bytes(response).await?;
+    context.server_handle.stop(true).await;
+
+    let memory: serde_json::Value = serde_json::from_slice(&body)?;
+    assert_eq!(status, hyper::StatusCode::OK);
+    let rss_bytes = memory["rss_bytes"].as_u64().unwrap();
+    let virtual_bytes = memory["virtual_bytes"].as_u64().unwrap();
+    // At least a megabyte resident, not more than mapped
+    assert!(rss_bytes > 1 << 20);
+    assert!(rss_bytes <= virtual_bytes);
+    Ok(())
+}
+
+#[rstest]
+#[tokio::test]
+async fn test_write_abi_notice(
+    context_future: impl Future<Output = Context>,
+) -> Result<(), Box<dyn std::error::Error>> {
+    let context = context_future.await;
+    let request = serde_json::json!({
+        "event_signature": "Transfer(address,int64,uint256,string)",
+        "args": ["0x0000000000000000000000000000000000000001", -5, "1000000000000000000000", "abi notice"],
+    });
+    let response = post_request(&context.address, "/notice/abi", request.to_string()).await;
+    let status = response.status();
+    let invalid_request = serde_json::json!({
+        "event_signature": "Transfer(address)",
+        "args": ["0x01"],
+    });
+    let invalid_response =
+        post_request(&context.address, "/notice/abi", invalid_request.to_string()).await;
+    context.server_handle.stop(true).await;
+
+    let data = std::fs::read("none.output-0.bin").expect("error reading test notice file");
+    let decoded_notice = ethabi::decode(
+        &[ethabi::ParamType::Bytes],
+        &data[4..], // skip the first 4 bytes that are the function signature
+    )?;
+    let params = [
+        ethabi::ParamType::Address,
+        ethabi::ParamType::Int(64),
+        ethabi::ParamType::Uint(256),
+        ethabi::ParamType::String,
+    ];
+    let mut expected = ethabi::long_signature("Transfer", &params)
+        .as_bytes()
+        .to_vec();
+    expected.extend(ethabi::encode(&[
+        ethabi::Token::Address(ethabi::Address::from_low_u64_be(1)),
+        ethabi::Token::Int(ethabi::Int::MAX - 4), // -5 in two's complement
+        ethabi::Token::Uint(ethabi::Uint::exp10(21)),
+        ethabi::Token::String("abi notice".to_string()),
+    ]));
+
+    assert_eq!(status, hyper::StatusCode::CREATED);
+    assert_eq!(invalid_response.status(), hyper::StatusCode::BAD_REQUEST);
+    assert_eq!(decoded_notice[0], ethabi::Token::Bytes(expected));
+    std::fs::remove_file("none.output-0.bin")?;
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_gio_http_domain() -> Result<(), Box<dyn std::error::Error>> {
+    // Upstream service echoing the forwarded request
+    let upstream = actix_web::HttpServer::new(|| {
+        actix_web::App::new().route(
+            "/gio",
+            actix_web::web::post().to(|body: actix_web::web::Bytes| async move {
+                actix_web::HttpResponse::Ok().body(body)
+            }),
+        )
+    })
+    .bind((HOST, 0))?;
+    let upstream_address = upstream.addrs()[0];
+    let upstream = upstream.run();
+    let upstream_handle = upstream.handle();
+    tokio::spawn(upstream);
+
+    let context = create_context(Config {
+        gio_http_domains: [(0x20, format!("http://{}/gio", upstream_address))].into(),
+        ..Config::new()
+    })
+    .await;
+    let request = GIORequest {
+        domain: 0x20,
+        id: "0x".to_string() + &hex::encode("gio http payload"),
+    };
+    let response =
+        rollup_http_client::client::send_gio_request(&context.address, request.clone()).await;
+    let status = response.status();
+    let body = hyper::body::to_bytes(response).await?;
+    context.server_handle.stop(true).await;
+    upstream_handle.stop(true).await;
+
+    let gio_response: serde_json::Value = serde_json::from_slice(&body)?;
+    assert_eq!(status, hyper::StatusCode::ACCEPTED);
+    assert_eq!(gio_response["response_code"], 200);
+    assert_eq!(
+        gio_response["response"],
+        format!("0x{}", hex::encode(serde_json::to_string(&request)?))
+    );
+    assert!(!std::path::Path::new("none.gio-0.bin").exists());
+
+    let invalid_config = Config {
+        gio_http_domains: [(0x20, "ftp://localhost/gio".to_string())].into(),
+        ..Config::new()
+    };
+    assert!(run_test_http_service(&invalid_config).is_err());
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_concurrent_gio_http_requests() -> Result<(), Box<dyn std::error::Error>> {
+    // Upstream service echoing the forwarded request after half a second
+    let upstream = actix_web::HttpServer::new(|| {
+        actix_web::App::new().route(
+            "/gio",
+            actix_web::web::post().to(|body: actix_web::web::Bytes| async move {
+                tokio::time::sleep(std::time::Duration::from_millis(500)).await;
+                actix_web::HttpResponse::Ok().body(body)
+            }),
+        )
+    })
+    .bind((HOST, 0))?;
+    let upstream_address = upstream.addrs()[0];
+    let upstream = upstream.run();
+    let upstream_handle = upstream.handle();
+    tokio::spawn(upstream);
+
+    let context = create_context(Config {
+        gio_http_domains: [(0x20, format!("http://{}/gio", upstream_address))].into(),
+        ..Config::new()
+    })
+    .await;
+    let requests: Vec<GIORequest> = (0..4)
+        .map(|index| GIORequest {
+            domain: 0x20,
+            id: "0x".to_string() + &hex::encode(format!("concurrent gio {}", index)),
+        })
+        .collect();
+    let started = std::time::Instant::now();
+    let responses = futures_util::future::join_all(requests.iter().map(|request| {
+        rollup_http_client::client::send_gio_request(&context.address, request.clone())
+    }))
+    .await;
+    let elapsed = started.elapsed();
+    let mut bodies = Vec::new();
+    for response in responses {
+        assert_eq!(response.status(), hyper::StatusCode::ACCEPTED);
+        bodies.push(hyper::body::to_bytes(response).await?);
+    }
+    context.server_handle.stop(true).await;
+    upstream_handle.stop(true).await;
+
+    // Serialized requests would take two seconds
+    assert!(elapsed < std::time::Duration::from_millis(1500));
+    for (request, body) in requests.iter().zip(bodies) {
+        let gio_response: serde_json::Value = serde_json::from_slice(&body)?;
+        assert_eq!(
+            gio_response["response"],
+            format!("0x{}", hex::encode(serde_json::to_string(request)?))
+        );
+    }
+    Ok(())
+}
+
+#[rstest]
+#[tokio::test]
+async fn test_payload_too_large(
+    context_future: impl Future<Output = Context>,
+) -> Result<(), Box<dyn std::error::Error>> {
+    let context = context_future.await;
+    let notice = Notice {
+        payload: "0x".to_string() + &"00".repeat(20000),
+    };
+    let body = serde_json::to_string(&notice)?;
+    let body_length = body.len();
+    let response = post_request(&context.address, "/notice", body).await;
+    let status = response.status();
+    let message = error_description(response).await?;
+    context.server_handle.stop(true).await;
+
+    assert_eq!(status, hyper::StatusCode::PAYLOAD_TOO_LARGE);
+    assert_eq!(
+        message,
+        format!(
+            "request body of {} bytes exceeds the limit of 32768 bytes",
+            body_length
+        )
+    );
+    Ok(())
+}
+
+#[test]
+fn test_startup_banner() -> Result<(), Box<dyn std::error::Error>> {
+    // without a dapp command the server exits right after logging the banner
+    let output = std::process::Command::new(env!("CARGO_BIN_EXE_rollup-http-server")).output()?;
+    let logs = String::from_utf8(output.stderr)?;
+
+    assert!(!output.status.success());
+    assert!(logs.contains(&format!("rollup-http-server {}", env!("CARGO_PKG_VERSION"))));
+    assert!(logs.contains("commit "));
+    Ok(())
+}
+
+#[rstest]
+#[tokio::test]
+async fn test_endpoint_size_limits(
+    #[with(Config {
+        max_notice_size: Some(1024),
+        max_report_size: Some(64 * 1024),
+        ..Config::new()
+    })]
+    context_future: impl Future<Output = Context>,
+) -> Result<(), Box<dyn std::error::Error>> {
+    let context = context_future.await;
+    let small = serde_json::json!({ "payload": "0x".to_string() + &"00".repeat(400) });
+    let large = serde_json::json!({ "payload": "0x".to_string() + &"00".repeat(20000) });
+    let small_notice = post_request(&context.address, "/notice", small.to_string()).await;
+    let large_notice = post_request(&context.address, "/notice", large.to_string()).await;
+    let large_notice_status = large_notice.status();
+    let message = error_description(large_notice).await?;
+    // Above the default limit, within the report one
+    let large_report = post_request(&context.address, "/report", large.to_string()).await;
+    context.server_handle.stop(true).await;
+    std::fs::remove_file("none.output-0.bin")?;
+    std::fs::remove_file("none.report-0.bin")?;
+
+    assert_eq!(small_notice.status(), hyper::StatusCode::CREATED);
+    assert_eq!(large_notice_status, hyper::StatusCode::PAYLOAD_TOO_LARGE);
+    assert_eq!(
+        message,
+        format!(
+            "request body of {} bytes exceeds the limit of 1024 bytes",
+            large.to_string().len()
+        )
+    );
+    assert_eq!(large_report.status(), hyper::StatusCode::ACCEPTED);
+    Ok(())
+}
+
+#[rstest]
+#[tokio::test]
+async fn test_payload_download(
+    #[with(Config {
+        payload_download_threshold: Some(128),
+        ..Config::new()
+    })]
+    context_future: impl Future<Output = Context>,
+) -> Result<(), Box<dyn std::error::Error>> {
+    let advance_path = "download_advance.bin";
+    let payload: Vec<u8> = (0..=255).collect();
+    write_advance_input_with_payload(advance_path, &payload)?;
+    env::set_var("CMT_INPUTS", format!("0:{}", advance_path));
+
+    let context = context_future.await;
+    let response = post_request(
+        &context.address,
+        "/finish",
+        "{\"status\":\"accept\"}".to_string(),
+    )
+    .await;
+    let finish: serde_json::Value =
+        serde_json::from_slice(&hyper::body::to_bytes(response).await?)?;
+    let payload_url = finish["payload_url"]
+        .as_str()
+        .unwrap_or_default()
+        .to_string();
+    let download = get_request(&context.address, &payload_url).await;
+    let download_status = download.status();
+    let downloaded = hyper::body::to_bytes(download).await?;
+    let unknown = get_request(&context.address, "/advance_payload/42").await;
+    context.server_handle.stop(true).await;
+
+    assert_eq!(finish["data"]["payload"], "0x");
+    assert_eq!(payload_url, "/advance_payload/0");
+    assert_eq!(download_status, hyper::StatusCode::OK);
+    assert_eq!(downloaded.to_vec(), payload);
+    assert_eq!(unknown.status(), hyper::StatusCode::NOT_FOUND);
+
+    std::fs::remove_file(advance_path)?;
+    Ok(())
+}
+
+#[rstest]
+#[tokio::test]
+async fn test_max_advance_payload_bytes(
+    #[with(Config {
+        max_advance_payload_bytes: Some(1024),
+        ..Config::new()
+    })]
+    context_future: impl Future<Output = Context>,
+) -> Result<(), Box<dyn std::error::Error>> {
+    let advance_path = "oversize_advance.bin";
+    write_advance_input_with_payload(advance_path, &[0xaa; 1025])?;
+    env::set_var("CMT_INPUTS", format!("0:{}", advance_path));
+
+    let context = context_future.await;
+    let response = post_request(
+        &context.address,
+        "/finish",
+        "{\"status\":\"accept\"}".to_string(),
+    )
+    .await;
+    let status = response.status();
+    let message = error_description(response).await?;
+    context.server_handle.stop(true).await;
+
+    assert_eq!(status, hyper::StatusCode::PAYLOAD_TOO_LARGE);
+    assert_eq!(
+        message,
+        "advance payload of 1025 bytes exceeds the limit of 1024 bytes"
+    );
+
+    std::fs::remove_file(advance_path)?;
+    Ok(())
+}
+
+#[test]
+fn test_log_timestamp_format() -> Result<(), Box<dyn std::error::Error>> {
+    let startup_log = |format: &str| -> Result<(bool, String), Box<dyn std::error::Error>> {
+        let output = std::process::Command::new(env!("CARGO_BIN_EXE_rollup-http-server"))
+            .args(["--log-timestamp-format", format])
+            .output()?;
+        let logs = String::from_utf8(output.stderr)?;
+        Ok((
+            output.status.success(),
+            logs.lines().next().unwrap_or("").to_string(),
+        ))
+    };
+
+    let (_, rfc3339) = startup_log("rfc3339")?;
+    let (_, epoch_millis) = startup_log("epoch-millis")?;
+    let (_, none) = startup_log("none")?;
+    let (success, invalid) = startup_log("iso")?;
+
+    let rfc3339_regex = regex::Regex::new(r"^\[\d{4}-\d{2}-\d{2}T\d{2}:\d{2}:\d{2}\.\d{3}Z INFO ")?;
+    let epoch_millis_regex = regex::Regex::new(r"^\[\d{13} INFO ")?;
+    assert!(rfc3339_regex.is_match(&rfc3339), "{}", rfc3339);
+    assert!(
+        epoch_millis_regex.is_match(&epoch_millis),
+        "{}",
+        epoch_millis
+    );
+    assert!(none.starts_with("[INFO "), "{}", none);
+    assert!(!success);
+    assert!(invalid.contains("unknown log timestamp format `iso`"));
+    Ok(())
+}
+
+#[rstest]
+#[tokio::test]
+async fn test_write_json_report(
+    context_future: impl Future<Output = Context>,
+) -> Result<(), Box<dyn std::error::Error>> {
+    let context = context_future.await;
+    let report = serde_json::json!({
+        "event": "json report",
+        "values": [1, 2, 3],
+        "nested": {"ok": true},
+    });
+    let response = post_request(&context.address, "/report/json", report.to_string()).await;
+    let status = response.status();
+    let body = hyper::body::to_bytes(response).await?;
+    context.server_handle.stop(true).await;
+
+    let stored = std::fs::read("none.report-0.bin").expect("error reading test report file");
+    let length: serde_json::Value = serde_json::from_slice(&body)?;
+    assert_eq!(status, hyper::StatusCode::ACCEPTED);
+    assert_eq!(length["length"], stored.len());
+    assert_eq!(
+        serde_json::from_slice::<serde_json::Value>(&stored)?,
+        report
+    );
+    std::fs::remove_file("none.report-0.bin")?;
+
+    Ok(())
+}
+
+/// Run the server with `fd` passed as a systemd socket, as systemd passes sockets starting at
+/// fd 3 and sets LISTEN_PID to the pid of the service. Exec keeps the pid of the shell
+fn systemd_activated_server(
+    fd: i32,
+    address: &str,
+    dapp: &[&str],
+) -> std::io::Result<std::process::Child> {
+    use std::os::unix::process::CommandExt;
+
+    let mut command = std::process::Command::new("sh");
+    command
+        .args([
+            "-c",
+            "export LISTEN_PID=$$ LISTEN_FDS=1; exec \"$0\" \"$@\"",
+            env!("CARGO_BIN_EXE_rollup-http-server"),
+            "--address",
+            address,
+        ])
+        .args(dapp)
+        .current_dir(std::env::temp_dir())
+        .stderr(std::process::Stdio::piped())
+        // The DApp is killed along with the server
+        .process_group(0);
+    unsafe {
+        command.pre_exec(move || {
+            let result = if fd == 3 {
+                libc::fcntl(3, libc::F_SETFD, 0)
+            } else {
+                libc::dup2(fd, 3)
+            };
+            if result == -1 {
+                return Err(std::io::Error::last_os_error());
+            }
+            Ok(())
+        });
+    }
+    command.spawn()
+}
+
+#[tokio::test]
+async fn test_systemd_socket_activation() -> Result<(), Box<dyn std::error::Error>> {
+    use std::io::Read;
+    use std::os::fd::AsRawFd;
+
+    let listener = std::net::TcpListener::bind((HOST, 0))?;
+    let local_address = listener.local_addr()?;
+    // The address given is taken, so binding it would fail
+    let inherited_path = std::env::temp_dir().join("systemd_socket_activation.dapp");
+    let _ = std::fs::remove_file(&inherited_path);
+    let mut server = systemd_activated_server(
+        listener.as_raw_fd(),
+        &local_address.to_string(),
+        &[
+            "sh",
+            "-c",
+            "{ [ -e /proc/$$/fd/3 ] && echo fd 3; env | grep ^LISTEN_; } > dapp.tmp; \
+             mv dapp.tmp systemd_socket_activation.dapp; exec sleep 30",
+        ],
+    )?;
+    drop(listener);
+
+    let response = get_request(&format!("http://{}", local_address), "/output_space").await;
+    let mut count = 50;
+    while !inherited_path.exists() && count > 0 {
+        tokio::time::sleep(std::time::Duration::from_millis(100)).await;
+        count -= 1;
+    }
+    let inherited = std::fs::read_to_string(&inherited_path)?;
+    std::fs::remove_file(&inherited_path)?;
+    unsafe { libc::kill(-(server.id() as i32), libc::SIGKILL) };
+    server.wait()?;
+
+    assert_eq!(response.status(), hyper::StatusCode::OK);
+    // Neither the socket nor the variables reach the DApp
+    assert_eq!(inherited, "");
+
+    // A fd that is not a socket is refused
+    let file = File::create(std::env::temp_dir().join("systemd_socket_activation.file"))?;
+    let mut server = systemd_activated_server(file.as_raw_fd(), "127.0.0.1:0", &["sleep", "30"])?;
+    drop(file);
+    let mut count = 50;
+    let status = loop {
+        match server.try_wait()? {
+            Some(status) => break Some(status),
+            None if count == 0 => break None,
+            None => tokio::time::sleep(std::time::Duration::from_millis(100)).await,
+        }
+        count -= 1;
+    };
+    if status.is_none() {
+        unsafe { libc::kill(-(server.id() as i32), libc::SIGKILL) };
+        server.wait()?;
+    }
+    let mut stderr = String::new();
+    server.stderr.take().unwrap().read_to_string(&mut stderr)?;
+    std::fs::remove_file(std::env::temp_dir().join("systemd_socket_activation.file"))?;
+
+    assert!(status.is_some(), "server started on an inherited file");
+    assert!(
+        stderr.contains("inherited socket 3 is not a socket"),
+        "{}",
+        stderr
+    );
+    Ok(())
+}
+
+#[rstest]
+#[tokio::test]
+async fn test_finish_output_indices(
+    #[with(Config {
+        finish_output_indices: true,
+        ..Config::new()
+    })]
+    context_future: impl Future<Output = Context>,
+) -> Result<(), Box<dyn std::error::Error>> {
+    let advance_path = "output_indices_advance.bin";
+    write_advance_input(advance_path)?;
+    env::set_var("CMT_INPUTS", format!("0:{0},0:{0}", advance_path));
+
+    let context = context_future.await;
+    let finish = "{\"status\":\"accept\"}".to_string();
+    let first_response = post_request(&context.address, "/finish", finish.clone()).await;
+    let first_request: serde_json::Value =
+        serde_json::from_slice(&hyper::body::to_bytes(first_response).await?)?;
+    let voucher = serde_json::json!({
+        "destination": "0x1111111111111111111111111111111111111111",
+        "value": "0x00000000000000000000000000000000000000000000000000000000deadbeef",
+        "payload": "0x".to_string() + &hex::encode("voucher"),
+    });
+    post_request(&context.address, "/voucher", voucher.to_string()).await;
+    let notice = serde_json::json!({"payload": "0x".to_string() + &hex::encode("notice")});
+    post_request(&context.address, "/notice", notice.to_string()).await;
+    let second_response = post_request(&context.address, "/finish", finish).await;
+    let second_request: serde_json::Value =
+        serde_json::from_slice(&hyper::body::to_bytes(second_response).await?)?;
+    context.server_handle.stop(true).await;
+
+    assert_eq!(first_request["request_type"], "advance_state");
+    assert_eq!(
+        first_request["previous_output_indices"],
+        serde_json::json!([])
+    );
+    assert_eq!(second_request["request_type"], "advance_state");
+    assert_eq!(
+        second_request["previous_output_indices"],
+        serde_json::json!([0, 1])
+    );
+
+    std::fs::remove_file("output_indices_advance.output-0.bin")?;
+    std::fs::remove_file("output_indices_advance.output-1.bin")?;
+    std::fs::remove_file("output_indices_advance.outputs_root_hash.bin")?;
+    std::fs::remove_file(advance_path)?;
+    Ok(())
+}
+
+#[rstest]
+#[tokio::test]
+async fn test_gio_cache(
+    #[with(Config {
+        gio_cache_domains: [0x20].into(),
+        ..Config::new()
+    })]
+    context_future: impl Future<Output = Context>,
+) -> Result<(), Box<dyn std::error::Error>> {
+    // the mock device answers a gio with the next input, so a second gio reaching it would fail
+    let advance_path = "gio_cache_advance.bin";
+    let response_path = "gio_cache_response.bin";
+    write_advance_input(advance_path)?;
+    std::fs::write(response_path, "cached gio response")?;
+    env::set_var(
+        "CMT_INPUTS",
+        format!("0:{0},0:{1},0:{0}", advance_path, response_path),
+    );
+
+    let context = context_future.await;
+    let request = GIORequest {
+        domain: 0x20,
+        id: "0x".to_string() + &hex::encode("gio cache id"),
+    };
+    let finish = "{\"status\":\"accept\"}".to_string();
+    post_request(&context.address, "/finish", finish.clone()).await;
+    let first_response =
+        rollup_http_client::client::send_gio_request(&context.address, request.clone()).await;
+    let first_status = first_response.status();
+    let first_body = hyper::body::to_bytes(first_response).await?;
+    let next_advance = post_request(&context.address, "/finish", finish).await;
+    let next_advance_status = next_advance.status();
+    let second_response =
+        rollup_http_client::client::send_gio_request(&context.address, request).await;
+    let second_status = second_response.status();
+    let second_body = hyper::body::to_bytes(second_response).await?;
+    context.server_handle.stop(true).await;
+
+    let gio_response: serde_json::Value = serde_json::from_slice(&first_body)?;
+    assert_eq!(first_status, hyper::StatusCode::ACCEPTED);
+    assert_eq!(next_advance_status, hyper::StatusCode::OK);
+    assert_eq!(second_status, hyper::StatusCode::ACCEPTED);
+    assert_eq!(
+        gio_response["response"],
+        format!("0x{}", hex::encode("cached gio response"))
+    );
+    assert_eq!(first_body, second_body);
+    assert!(!std::path::Path::new("gio_cache_advance.gio-1.bin").exists());
+
+    std::fs::remove_file("gio_cache_advance.gio-0.bin")?;
+    std::fs::remove_file("gio_cache_response.outputs_root_hash.bin")?;
+    std::fs::remove_file(response_path)?;
+    std::fs::remove_file(advance_path)?;
+    Ok(())
+}
+
+#[rstest]
+#[tokio::test]
+async fn test_time(
+    context_future: impl Future<Output = Context>,
+) -> Result<(), Box<dyn std::error::Error>> {
+    let advance_path = "time_advance.bin";
+    write_advance_input(advance_path)?;
+    env::set_var("CMT_INPUTS", format!("0:{}", advance_path));
+
+    let context = context_future.await;
+    let before_advance = get_request(&context.address, "/time").await;
+    let before_advance: serde_json::Value =
+        serde_json::from_slice(&hyper::body::to_bytes(before_advance).await?)?;
+    post_request(
+        &context.address,
+        "/finish",
+        "{\"status\":\"accept\"}".to_string(),
+    )
+    .await;
+    let after_advance = get_request(&context.address, "/time").await;
+    let after_advance: serde_json::Value =
+        serde_json::from_slice(&hyper::body::to_bytes(after_advance).await?)?;
+    context.server_handle.stop(true).await;
+
+    assert!(before_advance["server_time"].as_u64().unwrap() > 0);
+    assert!(before_advance["last_block_timestamp"].is_null());
+    assert!(after_advance["server_time"].as_u64().unwrap() > 0);
+    // block timestamp of ADVANCE_PAYLOAD_DATA
+    assert_eq!(after_advance["last_block_timestamp"], 5);
+
+    std::fs::remove_file(advance_path)?;
+    Ok(())
+}
+
+#[rstest]
+#[tokio::test]
+async fn test_pretty_json(
+    context_future: impl Future<Output = Context>,
+) -> Result<(), Box<dyn std::error::Error>> {
+    let context = context_future.await;
+    let compact = get_request(&context.address, "/output_space").await;
+    let compact = hyper::body::to_bytes(compact).await?;
+    let pretty = get_request(&context.address, "/output_space?pretty=true").await;
+    let pretty = hyper::body::to_bytes(pretty).await?;
+    context.server_handle.stop(true).await;
+
+    let compact = String::from_utf8(compact.to_vec())?;
+    let pretty = String::from_utf8(pretty.to_vec())?;
+    assert!(!compact.contains('\n'));
+    assert!(pretty.contains("\n  \"buffer_size\": "));
+    assert_eq!(
+        serde_json::from_str::<serde_json::Value>(&compact)?,
+        serde_json::from_str::<serde_json::Value>(&pretty)?
+    );
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_max_uptime() -> Result<(), Box<dyn std::error::Error>> {
+    let http_config = Config {
+        http_port: 0,
+        max_uptime_secs: Some(1),
+        ..Config::new()
+    };
+    let rollup_fd: Arc<Mutex<RollupFd>> = Arc::new(Mutex::new(create_rollup_fd()));
+    let server = http_service::create_server(&http_config, rollup_fd)?;
+
+    // the server future completes once the scheduled shutdown happens
+    let result = tokio::time::timeout(std::time::Duration::from_secs(10), server).await;
+    assert!(matches!(result, Ok(Ok(()))));
+    Ok(())
+}
+
+#[rstest]
+#[tokio::test]
+async fn test_validation_error(
+    context_future: impl Future<Output = Context>,
+) -> Result<(), Box<dyn std::error::Error>> {
+    let context = context_future.await;
+    let voucher = serde_json::json!({
+        "destination": "0xnot an address",
+        "value": "deadbeef",
+        "payload": "0x00",
+    });
+    let response = post_request(&context.address, "/voucher", voucher.to_string()).await;
+    let status = response.status();
+    let body = hyper::body::to_bytes(response).await?;
+    context.server_handle.stop(true).await;
+
+    let error: serde_json::Value = serde_json::from_slice(&body)?;
+    assert_eq!(status, hyper::StatusCode::BAD_REQUEST);
+    assert_eq!(
+        error,
+        serde_json::json!({
+            "error": {
+                "code": 400,
+                "reason": "/voucher: Bad Request",
+                "description": "destination: must be 42 characters long, got 16; value: length, regex",
+            }
+        })
+    );
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_gio_domain_timeouts() -> Result<(), Box<dyn std::error::Error>> {
+    // Upstream service taking half a second to answer
+    let upstream = actix_web::HttpServer::new(|| {
+        actix_web::App::new().route(
+            "/gio",
+            actix_web::web::post().to(|| async {
+                tokio::time::sleep(std::time::Duration::from_millis(500)).await;
+                actix_web::HttpResponse::Ok().body("slow response")
+            }),
+        )
+    })
+    .bind((HOST, 0))?;
+    let upstream_url = format!("http://{}/gio", upstream.addrs()[0]);
+    let upstream = upstream.run();
+    let upstream_handle = upstream.handle();
+    tokio::spawn(upstream);
+
+    let context = create_context(Config {
+        gio_http_domains: [(0x21, upstream_url.clone()), (0x22, upstream_url)].into(),
+        gio_domain_timeouts: [(0x21, 5000)].into(),
+        gio_default_timeout_ms: Some(100),
+        ..Config::new()
+    })
+    .await;
+    let gio_request = |domain| GIORequest {
+        domain,
+        id: "0x".to_string() + &hex::encode("gio timeout"),
+    };
+    let patient =
+        rollup_http_client::client::send_gio_request(&context.address, gio_request(0x21)).await;
+    let patient_status = patient.status();
+    let hasty =
+        rollup_http_client::client::send_gio_request(&context.address, gio_request(0x22)).await;
+    let hasty_status = hasty.status();
+    let hasty_message = error_description(hasty).await?;
+    context.server_handle.stop(true).await;
+    upstream_handle.stop(true).await;
+
+    assert_eq!(patient_status, hyper::StatusCode::ACCEPTED);
+    assert_eq!(hasty_status, hyper::StatusCode::GATEWAY_TIMEOUT);
+    assert_eq!(
+        hasty_message,
+        "gio request of domain 34 timed out after 100 ms"
+    );
+    Ok(())
+}
+
+#[rstest]
+#[tokio::test]
+async fn test_connections(
+    context_future: impl Future<Output = Context>,
+) -> Result<(), Box<dyn std::error::Error>> {
+    let context = context_future.await;
+    let active_connections = || async {
+        let response = get_request(&context.address, "/connections").await;
+        let body = hyper::body::to_bytes(response).await.unwrap();
+        let connections: serde_json::Value = serde_json::from_slice(&body).unwrap();
+        connections["active_connections"].as_u64().unwrap()
+    };
+    let server_address = context.address.trim_start_matches("http://").to_string();
+    let first = std::net::TcpStream::connect(&server_address)?;
+    let second = std::net::TcpStream::connect(&server_address)?;
+    // the two idle connections plus the one asking
+    let with_idle_connections = active_connections().await;
+    drop(first);
+    drop(second);
+    let mut count = 50;
+    let mut after_close = active_connections().await;
+    while after_close != 1 && count > 0 {
+        tokio::time::sleep(std::time::Duration::from_millis(100)).await;
+        after_close = active_connections().await;
+        count -= 1;
+    }
+    context.server_handle.stop(true).await;
+
+    assert_eq!(with_idle_connections, 3);
+    assert_eq!(after_close, 1);
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_log_output_indices() -> Result<(), Box<dyn std::error::Error>> {
+    let port = rand::thread_rng().gen_range(49152..65535);
+    let address = format!("{}:{}", HOST, port);
+    let output_dir = std::env::temp_dir().join("log-output-indices");
+    std::fs::create_dir_all(&output_dir)?;
+    let mut server = std::process::Command::new(env!("CARGO_BIN_EXE_rollup-http-server"))
+        // the server keeps serving after the dapp exits, a long running dapp would hold stderr
+        .args(["--address", &address, "--log-output-indices", "true"])
+        .current_dir(&output_dir)
+        .stderr(std::process::Stdio::piped())
+        .spawn()?;
+
+    let notice = serde_json::json!({"payload": "0x".to_string() + &hex::encode("logged")});
+    let mut count = 50;
+    let status = loop {
+        let client = hyper::Client::new();
+        let request = hyper::Request::builder()
+            .method(hyper::Method::POST)
+            .header(hyper::header::CONTENT_TYPE, "application/json")
+            .uri(format!("http://{}/notice", address))
+            .body(hyper::Body::from(notice.to_string()))?;
+        match client.request(request).await {
+            Ok(response) => break response.status(),
+            Err(_) if count > 0 => {
+                tokio::time::sleep(std::time::Duration::from_millis(100)).await;
+                count -= 1;
+            }
+            Err(e) => return Err(e.into()),
+        }
+    };
+    server.kill()?;
+    let output = server.wait_with_output()?;
+    std::fs::remove_dir_all(&output_dir)?;
+
+    assert_eq!(status, hyper::StatusCode::CREATED);
+    assert!(String::from_utf8(output.stderr)?.contains("notice written with index 0"));
+    Ok(())
+}
+
+#[rstest]
+#[tokio::test]
+async fn test_tcp_nodelay(
+    #[with(Config {
+        tcp_nodelay: true,
+        ..Config::new()
+    })]
+    context_future: impl Future<Output = Context>,
+) -> Result<(), Box<dyn std::error::Error>> {
+    let context = context_future.await;
+    let response = get_request(&context.address, "/output_space").await;
+    context.server_handle.stop(true).await;
+
+    assert_eq!(response.status(), hyper::StatusCode::OK);
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_connection_idle_timeout() -> Result<(), Box<dyn std::error::Error>> {
+    use tokio::io::{AsyncReadExt, AsyncWriteExt};
+
+    let advance_path = "idle_timeout_advance.bin";
+    write_advance_input(advance_path)?;
+    env::set_var("CMT_INPUTS", format!("0:{}", advance_path));
+    let rollup_fd = Arc::new(Mutex::new(create_rollup_fd()));
+    let port = rand::thread_rng().gen_range(49152..65535);
+    let config = Config {
+        http_address: HOST.to_string(),
+        http_port: port,
+        connection_idle_timeout_secs: Some(1),
+        ..Config::new()
+    };
+    let server = http_service::create_server(&config, rollup_fd.clone())?;
+    let server_handle = server.handle();
+    tokio::spawn(server);
+    let mut buffer = [0u8; 4096];
+
+    let mut idle = tokio::net::TcpStream::connect((HOST, port)).await?;
+    idle.write_all(b"GET /healthz HTTP/1.1\r\nHost: localhost\r\n\r\n")
+        .await?;
+    let health_read = idle.read(&mut buffer).await?;
+    let health = String::from_utf8_lossy(&buffer[..health_read]).to_string();
+
+    // The finish waits on the held rollup device past the idle timeout
+    let held = rollup_fd.lock().await;
+    let finish = "{\"status\":\"accept\"}";
+    let mut polling = tokio::net::TcpStream::connect((HOST, port)).await?;
+    polling
+        .write_all(
+            format!(
+                "POST /finish HTTP/1.1\r\nHost: localhost\r\nContent-Type: application/json\r\n\
+                 Content-Length: {}\r\n\r\n{}",
+                finish.len(),
+                finish
+            )
+            .as_bytes(),
+        )
+        .await?;
+    tokio::time::sleep(std::time::Duration::from_secs(2)).await;
+    let idle_read = idle.read(&mut buffer).await?;
+    drop(held);
+    let finish_read = polling.read(&mut buffer).await?;
+    let finish_response = String::from_utf8_lossy(&buffer[..finish_read]).to_string();
+    server_handle.stop(true).await;
+    std::fs::remove_file(advance_path)?;
+
+    assert!(health.starts_with("HTTP/1.1 200"));
+    // Closed by the server
+    assert_eq!(idle_read, 0);
+    assert!(finish_response.starts_with("HTTP/1.1 200"));
+    Ok(())
+}
+
+#[test]
+fn test_config_from_file() -> Result<(), Box<dyn std::error::Error>> {
+    let path = env::temp_dir().join(format!("rollup-http-server-{}.toml", rand::random::<u32>()));
+    std::fs::write(
+        &path,
+        "http_port = 5010\n\
+         max_pending_outputs = 8\n\
+         gio_cache_ttl = 60\n\
+         log_timestamp_format = \"rfc3339\"\n\
+         \n\
+         [gio_http_domains]\n\
+         1 = \"http://127.0.0.1:8080\"\n",
+    )?;
+    env::set_var("ROLLUP_HTTP_SERVER_MAX_PENDING_OUTPUTS", "16");
+    env::set_var("ROLLUP_HTTP_SERVER_READY_FILE", "/tmp/ready");
+    let config = Config::from_file(&path);
+    env::remove_var("ROLLUP_HTTP_SERVER_MAX_PENDING_OUTPUTS");
+    env::remove_var("ROLLUP_HTTP_SERVER_READY_FILE");
+    std::fs::remove_file(&path)?;
+    let config = config?;
+
+    // Values of the file replace the defaults
+    assert_eq!(config.http_port, 5010);
+    assert_eq!(config.http_address, Config::new().http_address);
+    assert_eq!(
+        config.gio_cache_ttl,
+        Some(std::time::Duration::from_secs(60))
+    );
+    assert_eq!(
+        config.log_timestamp_format,
+        rollup_http_server::config::LogTimestampFormat::Rfc3339
+    );
+    assert_eq!(
+        config.gio_http_domains.get(&1).map(String::as_str),
+        Some("http://127.0.0.1:8080")
+    );
+    // Environment variables replace the values of the file
+    assert_eq!(config.max_pending_outputs, Some(16));
+    assert_eq!(config.ready_file.as_deref(), Some("/tmp/ready"));
+    Ok(())
+}
+
+#[test]
+fn test_config_from_invalid_file() -> Result<(), Box<dyn std::error::Error>> {
+    let path = env::temp_dir().join(format!("rollup-http-server-{}.toml", rand::random::<u32>()));
+    std::fs::write(&path, "log_timestamp_format = \"iso\"\n")?;
+    let result = Config::from_file(&path);
+    std::fs::remove_file(&path)?;
+
+    let error = result.expect_err("invalid config file was accepted");
+    assert_eq!(error.kind(), std::io::ErrorKind::InvalidData);
+    assert!(error
+        .to_string()
+        .contains("unknown log timestamp format `iso`"));
+    Ok(())
+}
+
+#[rstest]
+#[tokio::test]
+async fn test_mirror_reports_to_notices(
+    #[with(Config {
+        mirror_reports_to_notices: true,
+        ..Config::new()
+    })]
+    context_future: impl Future<Output = Context>,
+) -> Result<(), Box<dyn std::error::Error>> {
+    let context = context_future.await;
+    let test_report = Report {
+        payload: "0x".to_string() + &hex::encode("mirrored report"),
+    };
+    rollup_http_client::client::send_report(&context.address, test_report).await;
+    context.server_handle.stop(true).await;
+
+    let report = std::fs::read_to_string("none.report-0.bin")?;
+    let notice = std::fs::read("none.output-0.bin")?;
+    std::fs::remove_file("none.report-0.bin")?;
+    std::fs::remove_file("none.output-0.bin")?;
+
+    assert_eq!(report, "mirrored report");
+    let decoded_notice = ethabi::decode(&[ethabi::ParamType::Bytes], &notice[4..])?;
+    assert_eq!(
+        decoded_notice[0],
+        ethabi::Token::Bytes(b"mirrored report".to_vec())
+    );
+    Ok(())
+}
+
+#[rstest]
+#[tokio::test]
+async fn test_strict_advance_metadata(
+    #[with(Config {
+        strict_advance_metadata: true,
+        finish_output_indices: true,
+        ..Config::new()
+    })]
+    context_future: impl Future<Output = Context>,
+) -> Result<(), Box<dyn std::error::Error>> {
+    let first_path = "strict_metadata_first.bin";
+    let second_path = "strict_metadata_second.bin";
+    let third_path = "strict_metadata_third.bin";
+    write_advance_input_at_block(first_path, 10, b"first")?;
+    write_advance_input_at_block(second_path, 9, b"second")?;
+    write_advance_input_at_block(third_path, 11, b"third")?;
+    env::set_var(
+        "CMT_INPUTS",
+        format!("0:{},0:{},0:{}", first_path, second_path, third_path),
+    );
+
+    let context = context_future.await;
+    let finish = "{\"status\":\"accept\"}".to_string();
+    let first_response = post_request(&context.address, "/finish", finish.clone()).await;
+    let notice = serde_json::json!({ "payload": "0x01" }).to_string();
+    let notice_status = post_request(&context.address, "/notice", notice)
+        .await
+        .status();
+    let second_response = post_request(&context.address, "/finish", finish.clone()).await;
+    let second_status = second_response.status();
+    let message = error_description(second_response).await?;
+    // The next finish rejects the input the DApp did not get and passes the following one
+    let third_response = post_request(&context.address, "/finish", finish).await;
+    let third_status = third_response.status();
+    let third: serde_json::Value =
+        serde_json::from_slice(&hyper::body::to_bytes(third_response).await?)?;
+    context.server_handle.stop(true).await;
+
+    assert_eq!(first_response.status(), hyper::StatusCode::OK);
+    assert_eq!(notice_status, hyper::StatusCode::CREATED);
+    assert_eq!(second_status, hyper::StatusCode::UNPROCESSABLE_ENTITY);
+    assert_eq!(
+        message,
+        "invalid advance metadata: block_number 9 is lower than the block_number 10 of the previous advance"
+    );
+    assert_eq!(third_status, hyper::StatusCode::OK);
+    assert_eq!(third["data"]["metadata"]["block_number"], 11);
+    assert_eq!(third["previous_output_indices"], serde_json::json!([0]));
+    // Only accepted inputs get an outputs root hash
+    assert!(!std::path::Path::new("strict_metadata_second.outputs_root_hash.bin").exists());
+
+    std::fs::remove_file("strict_metadata_first.output-0.bin")?;
+    std::fs::remove_file("strict_metadata_first.outputs_root_hash.bin")?;
+    std::fs::remove_file(first_path)?;
+    std::fs::remove_file(second_path)?;
+    std::fs::remove_file(third_path)?;
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_server_header() -> Result<(), Box<dyn std::error::Error>> {
+    let server_header = |value: &str| {
+        let value = value.to_string();
+        async move {
+            let context = create_context(Config {
+                server_header: Some(value),
+                ..Config::new()
+            })
+            .await;
+            let response = get_request(&context.address, "/output_space").await;
+            context.server_handle.stop(true).await;
+            response.headers().get(hyper::header::SERVER).cloned()
+        }
+    };
+
+    assert_eq!(
+        server_header("rollup").await,
+        Some(hyper::header::HeaderValue::from_static("rollup"))
+    );
+    assert_eq!(server_header("").await, None);
+    Ok(())
+}
+
+#[rstest]
+#[tokio::test]
+async fn test_method_not_allowed(
+    #[with(Config {
+        allowed_methods: [("/exception".to_string(), Vec::new())].into(),
+        ..Config::new()
+    })]
+    context_future: impl Future<Output = Context>,
+) -> Result<(), Box<dyn std::error::Error>> {
+    let context = context_future.await;
+    let client = hyper::Client::new();
+    let request = hyper::Request::builder()
+        .method(hyper::Method::PUT)
+        .uri(context.address.clone() + "/voucher")
+        .body(hyper::Body::empty())?;
+    let voucher_response = client.request(request).await?;
+    let exception = serde_json::json!({"payload": "0x".to_string() + &hex::encode("exception")});
+    let exception_response =
+        post_request(&context.address, "/exception", exception.to_string()).await;
+    context.server_handle.stop(true).await;
+
+    assert_eq!(
+        voucher_response.status(),
+        hyper::StatusCode::METHOD_NOT_ALLOWED
+    );
+    assert_eq!(voucher_response.headers()[hyper::header::ALLOW], "POST");
+    assert_eq!(
+        exception_response.status(),
+        hyper::StatusCode::METHOD_NOT_ALLOWED
+    );
+    assert_eq!(exception_response.headers()[hyper::header::ALLOW], "");
+    Ok(())
+}
+
+#[rstest]
+#[tokio::test]
+async fn test_structured_errors(
+    context_future: impl Future<Output = Context>,
+) -> Result<(), Box<dyn std::error::Error>> {
+    let context = context_future.await;
+    let unknown = get_request(&context.address, "/unknown").await;
+    let unknown_status = unknown.status();
+    let unknown_type = unknown.headers()[hyper::header::CONTENT_TYPE].clone();
+    let unknown_body = hyper::body::to_bytes(unknown).await?;
+    let method = get_request(&context.address, "/notice").await;
+    let method_allow = method.headers()[hyper::header::ALLOW].clone();
+    let method_body = hyper::body::to_bytes(method).await?;
+    context.server_handle.stop(true).await;
+
+    assert_eq!(unknown_status, hyper::StatusCode::NOT_FOUND);
+    assert_eq!(unknown_type, "application/json");
+    assert_eq!(
+        serde_json::from_slice::<serde_json::Value>(&unknown_body)?,
+        serde_json::json!({
+            "error": {
+                "code": 404,
+                "reason": "/unknown: Not Found",
+                "description": "Not Found",
+            }
+        })
+    );
+    // Headers set by the failing handler are kept
+    assert_eq!(method_allow, "POST");
+    let method_error: serde_json::Value = serde_json::from_slice(&method_body)?;
+    assert_eq!(method_error["error"]["code"], 405);
+    assert_eq!(
+        method_error["error"]["reason"],
+        "/notice: Method Not Allowed"
+    );
+    Ok(())
+}
+
+#[rstest]
+#[tokio::test]
+async fn test_async_outputs(
+    #[with(Config {
+        async_outputs: true,
+        ..Config::new()
+    })]
+    context_future: impl Future<Output = Context>,
+) -> Result<(), Box<dyn std::error::Error>> {
+    let context = context_future.await;
+    let notice = serde_json::json!({"payload": "0x".to_string() + &hex::encode("queued notice")});
+    let response = post_request(&context.address, "/notice", notice.to_string()).await;
+    assert_eq!(response.status(), hyper::StatusCode::ACCEPTED);
+    let response: serde_json::Value =
+        serde_json::from_slice(&hyper::body::to_bytes(response).await?)?;
+    let ticket = response["ticket"].as_u64().expect("ticket is not a number");
+
+    let mut count = 50;
+    let status = loop {
+        let response = get_request(&context.address, &format!("/output_status/{}", ticket)).await;
+        let status: serde_json::Value =
+            serde_json::from_slice(&hyper::body::to_bytes(response).await?)?;
+        if status["status"] != "pending" || count == 0 {
+            break status;
+        }
+        tokio::time::sleep(std::time::Duration::from_millis(100)).await;
+        count -= 1;
+    };
+    let unknown = get_request(&context.address, "/output_status/1000").await;
+    context.server_handle.stop(true).await;
+
+    assert_eq!(status, serde_json::json!({"status": "written", "index": 0}));
+    assert_eq!(unknown.status(), hyper::StatusCode::NOT_FOUND);
+    let data = std::fs::read("none.output-0.bin")?;
+    std::fs::remove_file("none.output-0.bin")?;
+    let decoded_notice = ethabi::decode(&[ethabi::ParamType::Bytes], &data[4..])?;
+    assert_eq!(
+        decoded_notice[0],
+        ethabi::Token::Bytes(b"queued notice".to_vec())
+    );
+    Ok(())
+}
+
+#[rstest]
+#[tokio::test]
+async fn test_async_outputs_order(
+    #[with(Config {
+        async_outputs: true,
+        ..Config::new()
+    })]
+    context_future: impl Future<Output = Context>,
+) -> Result<(), Box<dyn std::error::Error>> {
+    let context = context_future.await;
+    let hex_payload = |payload: &str| "0x".to_string() + &hex::encode(payload);
+    let mut tickets = Vec::new();
+    for (path, body) in [
+        (
+            "/notice",
+            serde_json::json!({ "payload": hex_payload("notice") }),
+        ),
+        (
+            "/notice/abi",
+            serde_json::json!({ "event_signature": "Log(string)", "args": ["abi notice"], "sequence": 1 }),
+        ),
+        ("/report/json", serde_json::json!({ "report": 0 })),
+        (
+            "/report_batch",
+            serde_json::json!({ "payloads": [hex_payload("batch 0"), hex_payload("batch 1")] }),
+        ),
+        (
+            "/notice",
+            serde_json::json!({ "payload": hex_payload("notice"), "sequence": 2 }),
+        ),
+    ] {
+        let response = post_request(&context.address, path, body.to_string()).await;
+        assert_eq!(response.status(), hyper::StatusCode::ACCEPTED, "{}", path);
+        let response: serde_json::Value =
+            serde_json::from_slice(&hyper::body::to_bytes(response).await?)?;
+        match response["tickets"].as_array() {
+            Some(batch_tickets) => {
+                tickets.extend(batch_tickets.iter().map(|t| t.as_u64().unwrap()))
+            }
+            None => tickets.push(response["ticket"].as_u64().expect("ticket is not a number")),
+        }
+    }
+    let mut statuses = Vec::new();
+    for ticket in &tickets {
+        let mut count = 50;
+        let status = loop {
+            let response =
+                get_request(&context.address, &format!("/output_status/{}", ticket)).await;
+            let status: serde_json::Value =
+                serde_json::from_slice(&hyper::body::to_bytes(response).await?)?;
+            if status["status"] != "pending" || count == 0 {
+                break status;
+            }
+            tokio::time::sleep(std::time::Duration::from_millis(100)).await;
+            count -= 1;
+        };
+        statuses.push(status);
+    }
+    context.server_handle.stop(true).await;
+
+    assert_eq!(tickets, vec![0, 1, 2, 3, 4, 5]);
+    // Outputs are written in the order they were queued
+    assert_eq!(
+        statuses[0],
+        serde_json::json!({"status": "written", "index": 0})
+    );
+    assert_eq!(
+        statuses[1],
+        serde_json::json!({"status": "written", "index": 1})
+    );
+    assert_eq!(
+        statuses[5],
+        serde_json::json!({"status": "written", "index": 2})
+    );
+    let reports = (0..3)
+        .map(|index| std::fs::read_to_string(format!("none.report-{}.bin", index)))
+        .collect::<Result<Vec<_>, _>>()?;
+    assert_eq!(reports, vec!["{\"report\":0}", "batch 0", "batch 1"]);
+    for index in 0..3 {
+        std::fs::remove_file(format!("none.output-{}.bin", index))?;
+        std::fs::remove_file(format!("none.report-{}.bin", index))?;
+    }
+    Ok(())
+}
+
+#[rstest]
+#[tokio::test]
+async fn test_watchdog(
+    #[with(Config {
+        watchdog_interval_secs: Some(1),
+        ..Config::new()
+    })]
+    context_future: impl Future<Output = Context>,
+) -> Result<(), Box<dyn std::error::Error>> {
+    let context = context_future.await;
+    let is_serving = |address: String| async move {
+        let uri = (address + "/healthz").parse::<hyper::Uri>().unwrap();
+        let request = hyper::Client::new().get(uri);
+        matches!(
+            tokio::time::timeout(std::time::Duration::from_secs(1), request).await,
+            Ok(Ok(response)) if response.status() == hyper::StatusCode::OK
+        )
+    };
+
+    // A responsive server passes the health checks
+    tokio::time::sleep(std::time::Duration::from_millis(2500)).await;
+    assert!(is_serving(context.address.clone()).await);
+    // A server that stops accepting connections fails them and gets shut down
+    context.server_handle.pause().await;
+    tokio::time::sleep(std::time::Duration::from_millis(3500)).await;
+    context.server_handle.resume().await;
+    assert!(!is_serving(context.address.clone()).await);
+    Ok(())
+}
+
+#[rstest]
+#[tokio::test]
+async fn test_current_input(
+    context_future: impl Future<Output = Context>,
+) -> Result<(), Box<dyn std::error::Error>> {
+    let advance_path = "current_input_advance.bin";
+    write_advance_input(advance_path)?;
+    env::set_var("CMT_INPUTS", format!("0:{}", advance_path));
+
+    let context = context_future.await;
+    let idle_response = get_request(&context.address, "/current_input").await;
+    post_request(
+        &context.address,
+        "/finish",
+        "{\"status\":\"accept\"}".to_string(),
+    )
+    .await;
+    let response = get_request(&context.address, "/current_input").await;
+    let status = response.status();
+    let current_input: serde_json::Value =
+        serde_json::from_slice(&hyper::body::to_bytes(response).await?)?;
+    context.server_handle.stop(true).await;
+
+    assert_eq!(idle_response.status(), hyper::StatusCode::NOT_FOUND);
+    assert_eq!(status, hyper::StatusCode::OK);
+    assert_eq!(current_input, serde_json::json!({"input_index": 7}));
+
+    std::fs::remove_file(advance_path)?;
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_path_normalization() -> Result<(), Box<dyn std::error::Error>> {
+    let voucher = serde_json::json!({
+        "destination": "0x1111111111111111111111111111111111111111",
+        "value": "0x00000000000000000000000000000000000000000000000000000000deadbeef",
+        "payload": "0x".to_string() + &hex::encode("trailing slash"),
+    });
+    let post_voucher = |path_normalization| {
+        let voucher = voucher.to_string();
+        async move {
+            let context = create_context(Config {
+                path_normalization,
+                ..Config::new()
+            })
+            .await;
+            let response = post_request(&context.address, "/voucher/", voucher).await;
+            context.server_handle.stop(true).await;
+            response.status()
+        }
+    };
+
+    assert_eq!(
+        post_voucher(rollup_http_server::config::PathNormalization::None).await,
+        hyper::StatusCode::NOT_FOUND
+    );
+    assert_eq!(
+        post_voucher(rollup_http_server::config::PathNormalization::Trim).await,
+        hyper::StatusCode::CREATED
+    );
+    std::fs::remove_file("none.output-0.bin")?;
+    Ok(())
+}
+
+#[rstest]
+#[tokio::test]
+async fn test_inspect_reports(
+    context_future: impl Future<Output = Context>,
+) -> Result<(), Box<dyn std::error::Error>> {
+    let inspect_path = "inspect_reports.bin";
+    std::fs::write(inspect_path, "inspect")?;
+    env::set_var("CMT_INPUTS", format!("1:{}", inspect_path));
+
+    let context = context_future.await;
+    let idle_response = get_request(&context.address, "/inspect/reports").await;
+    post_request(
+        &context.address,
+        "/finish",
+        "{\"status\":\"accept\"}".to_string(),
+    )
+    .await;
+    for payload in ["first report", "second report"] {
+        let report = Report {
+            payload: "0x".to_string() + &hex::encode(payload),
+        };
+        rollup_http_client::client::send_report(&context.address, report).await;
+    }
+    let response = get_request(&context.address, "/inspect/reports").await;
+    let reports: serde_json::Value =
+        serde_json::from_slice(&hyper::body::to_bytes(response).await?)?;
+    context.server_handle.stop(true).await;
+
+    assert_eq!(idle_response.status(), hyper::StatusCode::NOT_FOUND);
+    assert_eq!(
+        reports,
+        serde_json::json!([
+            "0x".to_string() + &hex::encode("first report"),
+            "0x".to_string() + &hex::encode("second report"),
+        ])
+    );
+
+    std::fs::remove_file("inspect_reports.report-0.bin")?;
+    std::fs::remove_file("inspect_reports.report-1.bin")?;
+    std::fs::remove_file(inspect_path)?;
+    Ok(())
+}
+
+#[test]
+fn test_keccak256() {
+    // Known vectors, met by every backend feature
+    assert_eq!(
+        hex::encode(hash::keccak256(b"")),
+        "c5d2460186f7233c927e7db2dcc703c0e500b653ca82273b7bfad8045d85a470"
+    );
+    assert_eq!(
+        hex::encode(hash::keccak256(b"abc")),
+        "4e03657aea45a94fc7d47ba826c8d667c0d1e6e33a64a036ec44f58fa12d6c45"
+    );
+    assert_eq!(
+        hex::encode(&hash::keccak256(b"Voucher(address,uint256,bytes)")[..4]),
+        "237a816f"
+    );
+}
+
+#[rstest]
+#[tokio::test]
+async fn test_report_batch(
+    context_future: impl Future<Output = Context>,
+) -> Result<(), Box<dyn std::error::Error>> {
+    let context = context_future.await;
+    let payloads = ["batched 0", "batched 1", "batched 2"];
+    let batch = serde_json::json!({
+        "payloads": payloads
+            .iter()
+            .map(|payload| "0x".to_string() + &hex::encode(payload))
+            .collect::<Vec<_>>(),
+    });
+    let response = post_request(&context.address, "/report_batch", batch.to_string()).await;
+    let status = response.status();
+    let count: serde_json::Value = serde_json::from_slice(&hyper::body::to_bytes(response).await?)?;
+    context.server_handle.stop(true).await;
+
+    assert_eq!(status, hyper::StatusCode::ACCEPTED);
+    assert_eq!(count, serde_json::json!({"count": 3}));
+    for (index, payload) in payloads.iter().enumerate() {
+        let path = format!("none.report-{}.bin", index);
+        assert_eq!(std::fs::read_to_string(&path)?, *payload);
+        std::fs::remove_file(&path)?;
+    }
+    Ok(())
+}
+
+#[rstest]
+#[tokio::test]
+async fn test_report_batch_limit(
+    #[with(Config {
+        max_pending_outputs: Some(4),
+        mirror_reports_to_notices: true,
+        ..Config::new()
+    })]
+    context_future: impl Future<Output = Context>,
+) -> Result<(), Box<dyn std::error::Error>> {
+    let context = context_future.await;
+    let batch = |count: usize| serde_json::json!({ "payloads": vec!["0x01"; count] }).to_string();
+    // Each report is mirrored by a notice, so three reports go beyond the limit of four outputs
+    let over_limit_status = post_request(&context.address, "/report_batch", batch(3))
+        .await
+        .status();
+    let at_limit_status = post_request(&context.address, "/report_batch", batch(2))
+        .await
+        .status();
+    let report = serde_json::json!({ "payload": "0x02" }).to_string();
+    let report_status = post_request(&context.address, "/report", report)
+        .await
+        .status();
+    context.server_handle.stop(true).await;
+
+    assert_eq!(over_limit_status, hyper::StatusCode::TOO_MANY_REQUESTS);
+    assert_eq!(at_limit_status, hyper::StatusCode::ACCEPTED);
+    assert_eq!(report_status, hyper::StatusCode::TOO_MANY_REQUESTS);
+    for index in 0..2 {
+        std::fs::remove_file(format!("none.report-{}.bin", index))?;
+        std::fs::remove_file(format!("none.output-{}.bin", index))?;
+    }
+    assert!(!std::path::Path::new("none.report-2.bin").exists());
+    Ok(())
+}
+
+async fn post_notice_with_key(address: &str, key: &str) -> hyper::Response<hyper::Body> {
+    let notice = Notice {
+        payload: "0x".to_string() + &hex::encode(key),
+    };
+    let client = hyper::Client::new();
+    let req = hyper::Request::builder()
+        .method(hyper::Method::POST)
+        .header(hyper::header::CONTENT_TYPE, "application/json")
+        .header("Idempotency-Key", key)
+        .uri(address.to_string() + "/notice")
+        .body(hyper::Body::from(serde_json::to_string(&notice).unwrap()))
+        .expect("http request");
+    client.request(req).await.expect("http response")
+}
+
+async fn clear_caches_request(address: &str, token: &str) -> hyper::Response<hyper::Body> {
+    let client = hyper::Client::new();
+    let req = hyper::Request::builder()
+        .method(hyper::Method::POST)
+        .header(hyper::header::AUTHORIZATION, format!("Bearer {}", token))
+        .uri(address.to_string() + "/admin/clear_caches")
+        .body(hyper::Body::empty())
+        .expect("http request");
+    client.request(req).await.expect("http response")
+}
+
+#[rstest]
+#[tokio::test]
+async fn test_clear_caches(
+    #[with(Config {
+        gio_cache_domains: [0x20].into(),
+        admin_token: Some("admin secret".to_string()),
+        ..Config::new()
+    })]
+    context_future: impl Future<Output = Context>,
+) -> Result<(), Box<dyn std::error::Error>> {
+    let response_path = "clear_caches_response.bin";
+    std::fs::write(response_path, "cached gio response")?;
+    env::set_var("CMT_INPUTS", format!("0:{}", response_path));
+
+    let context = context_future.await;
+    let request = GIORequest {
+        domain: 0x20,
+        id: "0x".to_string() + &hex::encode("gio cache id"),
+    };
+    let gio_response =
+        rollup_http_client::client::send_gio_request(&context.address, request).await;
+    let gio_status = gio_response.status();
+    let notice_status = post_notice_with_key(&context.address, "cached key")
+        .await
+        .status();
+    let unauthorized = clear_caches_request(&context.address, "wrong secret").await;
+    let unauthorized_status = unauthorized.status();
+    let cleared = clear_caches_request(&context.address, "admin secret").await;
+    let cleared_status = cleared.status();
+    let cleared: serde_json::Value =
+        serde_json::from_slice(&hyper::body::to_bytes(cleared).await?)?;
+    let emptied = clear_caches_request(&context.address, "admin secret").await;
+    let emptied: serde_json::Value =
+        serde_json::from_slice(&hyper::body::to_bytes(emptied).await?)?;
+    context.server_handle.stop(true).await;
+
+    assert_eq!(gio_status, hyper::StatusCode::ACCEPTED);
+    assert_eq!(notice_status, hyper::StatusCode::CREATED);
+    assert_eq!(unauthorized_status, hyper::StatusCode::UNAUTHORIZED);
+    assert_eq!(cleared_status, hyper::StatusCode::OK);
+    assert_eq!(
+        cleared,
+        serde_json::json!({ "gio_responses": 1, "idempotency_keys": 1, "output_statuses": 0 })
+    );
+    assert_eq!(
+        emptied,
+        serde_json::json!({ "gio_responses": 0, "idempotency_keys": 0, "output_statuses": 0 })
+    );
+
+    std::fs::remove_file("none.gio-0.bin")?;
+    std::fs::remove_file("clear_caches_response.output-0.bin")?;
+    std::fs::remove_file(response_path)?;
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_output_sequence_file() -> Result<(), Box<dyn std::error::Error>> {
+    let sequence_path = "test_output_sequence.json";
+    let config = Config {
+        output_sequence_file: Some(sequence_path.to_string()),
+        ..Config::new()
+    };
+    let context = create_context(config.clone()).await;
+    let response = post_notice_with_key(&context.address, "first notice").await;
+    let status = response.status();
+    let index: serde_json::Value = serde_json::from_slice(&hyper::body::to_bytes(response).await?)?;
+    context.server_handle.stop(true).await;
+    assert_eq!(status, hyper::StatusCode::CREATED);
+    assert_eq!(index, serde_json::json!({"index": 0}));
+    std::fs::remove_file("none.output-0.bin")?;
+
+    // A restarted server answers the retried notice without writing it again
+    let context = create_context(config).await;
+    let response = post_notice_with_key(&context.address, "first notice").await;
+    let status = response.status();
+    let index: serde_json::Value = serde_json::from_slice(&hyper::body::to_bytes(response).await?)?;
+    context.server_handle.stop(true).await;
+    assert_eq!(status, hyper::StatusCode::CREATED);
+    assert_eq!(index, serde_json::json!({"index": 0}));
+    assert!(!std::path::Path::new("none.output-0.bin").exists());
+    std::fs::remove_file(sequence_path)?;
+    Ok(())
+}
+
+#[rstest]
+#[tokio::test]
+async fn test_slow_start(
+    #[with(Config {
+        slow_start_secs: Some(2),
+        slow_start_rate: 1,
+        ..Config::new()
+    })]
+    context_future: impl Future<Output = Context>,
+) -> Result<(), Box<dyn std::error::Error>> {
+    let context = context_future.await;
+    let first = get_request(&context.address, "/time").await;
+    let second = get_request(&context.address, "/time").await;
+    let health = get_request(&context.address, "/healthz").await;
+    // The DApp outputs are never throttled, its client treating a 503 as an error
+    let notice = serde_json::json!({ "payload": "0x01" }).to_string();
+    let notice_status = post_request(&context.address, "/notice", notice)
+        .await
+        .status();
+    tokio::time::sleep(std::time::Duration::from_millis(2100)).await;
+    let mut later = Vec::new();
+    for _ in 0..5 {
+        later.push(get_request(&context.address, "/time").await.status());
+    }
+    context.server_handle.stop(true).await;
+
+    assert_eq!(first.status(), hyper::StatusCode::OK);
+    assert_eq!(second.status(), hyper::StatusCode::SERVICE_UNAVAILABLE);
+    assert_eq!(second.headers()[hyper::header::RETRY_AFTER], "1");
+    assert_eq!(health.status(), hyper::StatusCode::OK);
+    assert_eq!(notice_status, hyper::StatusCode::CREATED);
+    assert!(later.iter().all(|status| *status == hyper::StatusCode::OK));
+    std::fs::remove_file("none.output-0.bin")?;
+    Ok(())
+}
+
+#[rstest]
+#[tokio::test]
+async fn test_finish_without_waiting(
+    context_future: impl Future<Output = Context>,
+) -> Result<(), Box<dyn std::error::Error>> {
+    let advance_path = "no_wait_advance.bin";
+    write_advance_input(advance_path)?;
+    env::set_var("CMT_INPUTS", format!("0:{}", advance_path));
+
+    let context = context_future.await;
+    let before = get_request(&context.address, "/next_request").await;
+    let finish = post_request(
+        &context.address,
+        "/finish?wait=false",
+        "{\"status\":\"accept\"}".to_string(),
+    )
+    .await;
+    let mut count = 50;
+    let next = loop {
+        let response = get_request(&context.address, "/next_request").await;
+        if response.status() != hyper::StatusCode::NO_CONTENT || count == 0 {
+            break response;
+        }
+        tokio::time::sleep(std::time::Duration::from_millis(100)).await;
+        count -= 1;
+    };
+    let next_status = next.status();
+    let request: serde_json::Value = serde_json::from_slice(&hyper::body::to_bytes(next).await?)?;
+    let after = get_request(&context.address, "/next_request").await;
+    context.server_handle.stop(true).await;
+
+    assert_eq!(before.status(), hyper::StatusCode::NO_CONTENT);
+    assert_eq!(finish.status(), hyper::StatusCode::OK);
+    assert_eq!(next_status, hyper::StatusCode::OK);
+    assert_eq!(request["request_type"], "advance_state");
+    assert_eq!(after.status(), hyper::StatusCode::NO_CONTENT);
+    std::fs::remove_file(advance_path)?;
+    Ok(())
+}
+
+#[rstest]
+#[tokio::test]
+async fn test_rollup_errno_header(
+    context_future: impl Future<Output = Context>,
+) -> Result<(), Box<dyn std::error::Error>> {
+    // The mock device fails to store the report over a directory with EISDIR
+    let report_path = "none.report-0.bin";
+    std::fs::create_dir(report_path)?;
+    let context = context_future.await;
+    let report = Report {
+        payload: "0x".to_string() + &hex::encode("report over a directory"),
+    };
+    let response = post_request(&context.address, "/report", serde_json::to_string(&report)?).await;
+    context.server_handle.stop(true).await;
+    std::fs::remove_dir(report_path)?;
+
+    assert_eq!(response.status(), hyper::StatusCode::BAD_REQUEST);
+    assert_eq!(response.headers()["X-Rollup-Errno"], "21");
+    Ok(())
+}
+
+#[rstest]
+#[tokio::test]
+async fn test_gio_binary(
+    context_future: impl Future<Output = Context>,
+) -> Result<(), Box<dyn std::error::Error>> {
+    // The mock device answers each gio request with the next input
+    let response_path = "gio_response.bin";
+    std::fs::write(response_path, "gio response")?;
+    env::set_var("CMT_INPUTS", format!("0:{0},0:{0}", response_path));
+    let context = context_future.await;
+    let id = b"gio binary payload \x00\xff";
+    let json_request = GIORequest {
+        domain: 0x100,
+        id: "0x".to_string() + &hex::encode(id),
+    };
+    let json_response = post_request(
+        &context.address,
+        "/gio",
+        serde_json::to_string(&json_request)?,
+    )
+    .await;
+    let json_status = json_response.status();
+    let json_body = hyper::body::to_bytes(json_response).await?;
+    let binary_request = hyper::Request::builder()
+        .method(hyper::Method::POST)
+        .header(hyper::header::CONTENT_TYPE, "application/octet-stream")
+        .header("X-Gio-Domain", "0x100")
+        .uri(context.address.clone() + "/gio_binary")
+        .body(hyper::Body::from(id.to_vec()))?;
+    let binary_response = hyper::Client::new().request(binary_request).await?;
+    let binary_status = binary_response.status();
+    let binary_body = hyper::body::to_bytes(binary_response).await?;
+    let missing_domain = post_request(&context.address, "/gio_binary", "id".to_string()).await;
+    context.server_handle.stop(true).await;
+
+    assert_eq!(json_status, hyper::StatusCode::ACCEPTED);
+    assert_eq!(binary_status, json_status);
+    assert_eq!(binary_body, json_body);
+    assert_eq!(missing_domain.status(), hyper::StatusCode::BAD_REQUEST);
+    assert_eq!(std::fs::read("none.gio-0.bin")?, id);
+    assert_eq!(std::fs::read("gio_response.gio-1.bin")?, id);
+    std::fs::remove_file("none.gio-0.bin")?;
+    std::fs::remove_file("gio_response.gio-1.bin")?;
+    std::fs::remove_file(response_path)?;
+    Ok(())
+}
+
+#[rstest]
+#[tokio::test]
+async fn test_voucher_value(
+    context_future: impl Future<Output = Context>,
+) -> Result<(), Box<dyn std::error::Error>> {
+    let context = context_future.await;
+    let without_value = serde_json::json!({
+        "destination": "0x1111111111111111111111111111111111111111",
+        "payload": "0x00",
+    });
+    let response = post_request(&context.address, "/voucher", without_value.to_string()).await;
+    let without_value_status = response.status();
+    let short_value = serde_json::json!({
+        "destination": "0x1111111111111111111111111111111111111111",
+        "value": "0x01",
+        "payload": "0x00",
+    });
+    let response = post_request(&context.address, "/voucher", short_value.to_string()).await;
+    let short_value_status = response.status();
+    // As long as a value, without the 0x prefix at its start
+    let prefixed_value = serde_json::json!({
+        "destination": "0x1111111111111111111111111111111111111111",
+        "value": format!("zz0x{:062x}", 1),
+        "payload": "0x00",
+    });
+    let response = post_request(&context.address, "/voucher", prefixed_value.to_string()).await;
+    let prefixed_value_status = response.status();
+    context.server_handle.stop(true).await;
+
+    assert_eq!(without_value_status, hyper::StatusCode::CREATED);
+    assert_eq!(short_value_status, hyper::StatusCode::BAD_REQUEST);
+    assert_eq!(prefixed_value_status, hyper::StatusCode::BAD_REQUEST);
+    check_voucher_or_fail(
+        Voucher {
+            destination: "0x1111111111111111111111111111111111111111".to_string(),
+            value: format!("0x{:064x}", 0),
+            payload: "0x00".to_string(),
+        },
+        "none.output-0.bin",
+    );
+    assert!(!std::path::Path::new("none.output-1.bin").exists());
+    std::fs::remove_file("none.output-0.bin")?;
+    Ok(())
+}
+
+#[rstest]
+#[tokio::test]
+async fn test_voucher_destination(
+    context_future: impl Future<Output = Context>,
+) -> Result<(), Box<dyn std::error::Error>> {
+    let context = context_future.await;
+    let mut responses = Vec::new();
+    for destination in [
+        "0xAbCdEf0123456789aBcDeF0123456789ABCDEF01",
+        "0x1111",
+        "0xZZZZZZZZZZZZZZZZZZZZZZZZZZZZZZZZZZZZZZZZ",
+        "1111111111111111111111111111111111111111",
+    ] {
+        let voucher = serde_json::json!({
+            "destination": destination,
+            "payload": "0x00",
+        });
+        let response = post_request(&context.address, "/voucher", voucher.to_string()).await;
+        let status = response.status();
+        let body = hyper::body::to_bytes(response).await?;
+        responses.push((status, body));
+    }
+    context.server_handle.stop(true).await;
+    std::fs::remove_file("none.output-0.bin")?;
+
+    // Mixed case hex is accepted
+    assert_eq!(responses[0].0, hyper::StatusCode::CREATED);
+    for ((status, body), description) in responses[1..].iter().zip([
+        "destination: must be 42 characters long, got 6",
+        "destination: invalid hex character 'Z' at index 2",
+        "destination: must start with 0x",
+    ]) {
+        let error: serde_json::Value = serde_json::from_slice(body)?;
+        assert_eq!(*status, hyper::StatusCode::BAD_REQUEST);
+        assert_eq!(error["error"]["description"], description);
+    }
+    Ok(())
+}
+
+#[rstest]
+#[tokio::test]
+async fn test_checksum_addresses(
+    #[with(Config {
+        enforce_checksum_addresses: true,
+        ..Config::new()
+    })]
+    context_future: impl Future<Output = Context>,
+) -> Result<(), Box<dyn std::error::Error>> {
+    let context = context_future.await;
+    let mut responses = Vec::new();
+    for destination in [
+        "0x5aAeb6053F3E94C9b9A09f33669435E7Ef1BeAed",
+        "0x5aaeb6053f3e94c9b9a09f33669435e7ef1beaed",
+        "0x5AAEB6053F3E94C9B9A09F33669435E7EF1BEAED",
+        "0x5aAeb6053F3E94C9b9A09f33669435E7Ef1BeAeD",
+    ] {
+        let voucher = serde_json::json!({
+            "destination": destination,
+            "payload": "0x00",
+        });
+        let response = post_request(&context.address, "/voucher", voucher.to_string()).await;
+        let status = response.status();
+        responses.push((
+            status,
+            error_description(response).await.unwrap_or_default(),
+        ));
+    }
+    // The token and recipient of an ERC-20 transfer are checked too
+    let mut transfer_responses = Vec::new();
+    for (token, to) in [
+        (
+            "0x5aAeb6053F3E94C9b9A09f33669435E7Ef1BeAed",
+            "0x5aAeb6053F3E94C9b9A09f33669435E7Ef1BeAed",
+        ),
+        (
+            "0x5aAeb6053F3E94C9b9A09f33669435E7Ef1BeAeD",
+            "0x5aAeb6053F3E94C9b9A09f33669435E7Ef1BeAed",
+        ),
+        (
+            "0x5aAeb6053F3E94C9b9A09f33669435E7Ef1BeAed",
+            "0x5aAeb6053F3E94C9b9A09f33669435E7Ef1BeAeD",
+        ),
+    ] {
+        let transfer = serde_json::json!({ "token": token, "to": to, "amount": "1" });
+        let response = post_request(
+            &context.address,
+            "/voucher/erc20_transfer",
+            transfer.to_string(),
+        )
+        .await;
+        let status = response.status();
+        transfer_responses.push((
+            status,
+            error_description(response).await.unwrap_or_default(),
+        ));
+    }
+    context.server_handle.stop(true).await;
+    for index in 0..4 {
+        std::fs::remove_file(format!("none.output-{}.bin", index))?;
+    }
+
+    // Checksummed, all lowercase and all uppercase addresses are accepted
+    for (status, _) in &responses[..3] {
+        assert_eq!(*status, hyper::StatusCode::CREATED);
+    }
+    assert_eq!(
+        responses[3],
+        (
+            hyper::StatusCode::BAD_REQUEST,
+            "destination 0x5aAeb6053F3E94C9b9A09f33669435E7Ef1BeAeD fails the EIP-55 checksum, \
+             expected 0x5aAeb6053F3E94C9b9A09f33669435E7Ef1BeAed"
+                .to_string()
+        )
+    );
+    assert_eq!(transfer_responses[0].0, hyper::StatusCode::CREATED);
+    assert_eq!(
+        transfer_responses[1],
+        (
+            hyper::StatusCode::BAD_REQUEST,
+            "token 0x5aAeb6053F3E94C9b9A09f33669435E7Ef1BeAeD fails the EIP-55 checksum, \
+             expected 0x5aAeb6053F3E94C9b9A09f33669435E7Ef1BeAed"
+                .to_string()
+        )
+    );
+    assert_eq!(
+        transfer_responses[2],
+        (
+            hyper::StatusCode::BAD_REQUEST,
+            "to 0x5aAeb6053F3E94C9b9A09f33669435E7Ef1BeAeD fails the EIP-55 checksum, \
+             expected 0x5aAeb6053F3E94C9b9A09f33669435E7Ef1BeAed"
+                .to_string()
+        )
+    );
+    Ok(())
+}
+
+#[rstest]
+#[case::permissive(false, [true, true, true])]
+#[case::strict(true, [true, false, false])]
+#[tokio::test]
+async fn test_hex_strict(
+    #[case] hex_strict: bool,
+    #[case] accepted: [bool; 3],
+) -> Result<(), Box<dyn std::error::Error>> {
+    let context = create_context(Config {
+        hex_strict,
+        ..Config::new()
+    })
+    .await;
+    let mut statuses = Vec::new();
+    for payload in ["0xdeadbeef", "0xDEADBEEF", "0xdeadBEEF"] {
+        let body = serde_json::json!({ "payload": payload }).to_string();
+        let response = post_request(&context.address, "/report", body).await;
+        statuses.push(response.status());
+        if response.status() == hyper::StatusCode::BAD_REQUEST {
+            assert_eq!(
+                error_description(response).await?,
+                format!(
+                    "report payload has uppercase hex character {:?} at index {}, \
+                     only lowercase is accepted",
+                    payload.chars().find(char::is_ascii_uppercase).unwrap(),
+                    payload.find(|c: char| c.is_ascii_uppercase()).unwrap()
+                )
+            );
+        }
+    }
+    context.server_handle.stop(true).await;
+    for index in 0..accepted.iter().filter(|accepted| **accepted).count() {
+        std::fs::remove_file(format!("none.report-{}.bin", index))?;
+    }
+
+    for (status, accepted) in statuses.into_iter().zip(accepted) {
+        let expected = match accepted {
+            true => hyper::StatusCode::ACCEPTED,
+            false => hyper::StatusCode::BAD_REQUEST,
+        };
+        assert_eq!(status, expected);
+    }
+    Ok(())
+}
+
+#[rstest]
+#[tokio::test]
+async fn test_notice_sequence(
+    context_future: impl Future<Output = Context>,
+) -> Result<(), Box<dyn std::error::Error>> {
+    let context = context_future.await;
+    let mut statuses = Vec::new();
+    for notice in [
+        serde_json::json!({ "payload": "0x01", "sequence": 0 }),
+        // Sequence numbers are assigned to notices without one
+        serde_json::json!({ "payload": "0x02" }),
+        serde_json::json!({ "payload": "0x03", "sequence": 3 }),
+        serde_json::json!({ "payload": "0x03", "sequence": 2 }),
+    ] {
+        let response = post_request(&context.address, "/notice", notice.to_string()).await;
+        let status = response.status();
+        statuses.push((
+            status,
+            error_description(response).await.unwrap_or_default(),
+        ));
+    }
+    context.server_handle.stop(true).await;
+    for index in 0..3 {
+        std::fs::remove_file(format!("none.output-{}.bin", index))?;
+    }
+
+    assert_eq!(
+        statuses,
+        [
+            (hyper::StatusCode::CREATED, String::new()),
+            (hyper::StatusCode::CREATED, String::new()),
+            (
+                hyper::StatusCode::CONFLICT,
+                "notice sequence 3 is out of order, expected 2".to_string()
+            ),
+            (hyper::StatusCode::CREATED, String::new()),
+        ]
+    );
+    Ok(())
+}
+
+#[rstest]
+#[tokio::test]
+async fn test_invalid_payload(
+    context_future: impl Future<Output = Context>,
+) -> Result<(), Box<dyn std::error::Error>> {
+    let context = context_future.await;
+    let mut responses = Vec::new();
+    for (path, payload) in [
+        ("/notice", "0x00zz"),
+        ("/report", "deadbeef"),
+        ("/report", "0xabc"),
+    ] {
+        let body = serde_json::json!({ "payload": payload }).to_string();
+        let response = post_request(&context.address, path, body).await;
+        let status = response.status();
+        responses.push((status, error_description(response).await?));
+    }
+    context.server_handle.stop(true).await;
+
+    assert_eq!(
+        responses,
+        [
+            (
+                hyper::StatusCode::BAD_REQUEST,
+                "notice payload has invalid hex character 'z' at index 4".to_string()
+            ),
+            (
+                hyper::StatusCode::BAD_REQUEST,
+                "report payload must start with 0x".to_string()
+            ),
+            (
+                hyper::StatusCode::BAD_REQUEST,
+                "report payload has an odd number of hex digits".to_string()
+            ),
+        ]
+    );
+    assert!(!std::path::Path::new("none.output-0.bin").exists());
+    assert!(!std::path::Path::new("none.report-0.bin").exists());
+    Ok(())
+}
+
+#[rstest]
+#[tokio::test]
+async fn test_health_and_ready(
+    context_future: impl Future<Output = Context>,
+) -> Result<(), Box<dyn std::error::Error>> {
+    let context = context_future.await;
+    let health = get_request(&context.address, "/health").await;
+    let ready = get_request(&context.address, "/ready").await;
+    let exception = Exception {
+        payload: "0x".to_string() + &hex::encode("no more requests"),
+    };
+    rollup_http_client::client::throw_exception(&context.address, exception).await;
+    let health_after_exception = get_request(&context.address, "/health").await;
+    let ready_after_exception = get_request(&context.address, "/ready").await;
+    context.server_handle.stop(true).await;
+
+    assert_eq!(health.status(), hyper::StatusCode::OK);
+    assert_eq!(ready.status(), hyper::StatusCode::OK);
+    assert_eq!(health_after_exception.status(), hyper::StatusCode::OK);
+    assert_eq!(
+        ready_after_exception.status(),
+        hyper::StatusCode::SERVICE_UNAVAILABLE
+    );
+    std::fs::remove_file("none.exception-0.bin")?;
+    Ok(())
+}
+
+#[rstest]
+#[tokio::test]
+async fn test_lock_wait_timeout(
+    #[with(Config {
+        lock_wait_timeout_ms: Some(100),
+        ..Config::new()
+    })]
+    context_future: impl Future<Output = Context>,
+) -> Result<(), Box<dyn std::error::Error>> {
+    // Reading the next input from a fifo blocks the finish until it is written to
+    let fifo_path = "lock_wait_timeout.fifo";
+    assert!(std::process::Command::new("mkfifo")
+        .arg(fifo_path)
+        .status()?
+        .success());
+    env::set_var("CMT_INPUTS", format!("0:{}", fifo_path));
+
+    let context = context_future.await;
+    let address = context.address.clone();
+    let finish = tokio::spawn(async move {
+        post_request(&address, "/finish", "{\"status\":\"accept\"}".to_string())
+            .await
+            .status()
+    });
+    // Let the finish reach the rollup device
+    tokio::time::sleep(std::time::Duration::from_millis(200)).await;
+    let notice = serde_json::json!({ "payload": "0x00" }).to_string();
+    let contended = tokio::time::timeout(
+        std::time::Duration::from_secs(2),
+        post_request(&context.address, "/notice", notice),
+    )
+    .await?;
+    std::fs::write(fifo_path, b"fifo input")?;
+    finish.await?;
+    context.server_handle.stop(true).await;
+
+    assert_eq!(contended.status(), hyper::StatusCode::SERVICE_UNAVAILABLE);
+    assert_eq!(contended.headers()[hyper::header::RETRY_AFTER], "1");
+    std::fs::remove_file(fifo_path)?;
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_output_validator() -> Result<(), Box<dyn std::error::Error>> {
+    let rollup_fd = Arc::new(Mutex::new(create_rollup_fd()));
+    let port = rand::thread_rng().gen_range(49152..65535);
+    let config = Config {
+        http_address: HOST.to_string(),
+        http_port: port,
+        ..Config::new()
+    };
+    let server = http_service::ServerBuilder::new(&config, rollup_fd)
+        .output_validator(|kind, payload| match payload.contains(&0xff) {
+            true => Err(format!(
+                "{:?} payload contains the forbidden byte 0xff",
+                kind
+            )),
+            false => Ok(()),
+        })
+        .build()?;
+    let server_handle = server.handle();
+    tokio::spawn(server);
+    let address = format!("http://{}:{}", HOST, port);
+
+    let forbidden = serde_json::json!({ "payload": "0x00ff00" }).to_string();
+    let rejected = post_request(&address, "/notice", forbidden).await;
+    let rejected_status = rejected.status();
+    let rejected_message = error_description(rejected).await?;
+    let allowed = serde_json::json!({ "payload": "0x0000" }).to_string();
+    let accepted = post_request(&address, "/notice", allowed).await;
+    server_handle.stop(true).await;
+
+    assert_eq!(rejected_status, hyper::StatusCode::BAD_REQUEST);
+    assert_eq!(
+        rejected_message,
+        "notice rejected: Notice payload contains the forbidden byte 0xff"
+    );
+    assert_eq!(accepted.status(), hyper::StatusCode::CREATED);
+    // Only the allowed notice reached the rollup device
+    assert!(std::path::Path::new("none.output-0.bin").exists());
+    assert!(!std::path::Path::new("none.output-1.bin").exists());
+    std::fs::remove_file("none.output-0.bin")?;
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_metrics() -> Result<(), Box<dyn std::error::Error>> {
+    let context = create_context(Config::new()).await;
+    let disabled = get_request(&context.address, "/metrics").await;
+    context.server_handle.stop(true).await;
+    assert_eq!(disabled.status(), hyper::StatusCode::NOT_FOUND);
+
+    let context = create_context(Config {
+        enable_metrics: true,
+        ..Config::new()
+    })
+    .await;
+    let payload = serde_json::json!({ "payload": "0x00" }).to_string();
+    post_request(&context.address, "/notice", payload.clone()).await;
+    post_request(&context.address, "/report", payload.clone()).await;
+    post_request(&context.address, "/report", payload).await;
+    let response = get_request(&context.address, "/metrics").await;
+    let status = response.status();
+    let metrics = String::from_utf8(hyper::body::to_bytes(response).await?.to_vec())?;
+    context.server_handle.stop(true).await;
+
+    assert_eq!(status, hyper::StatusCode::OK);
+    let lines: Vec<&str> = metrics.lines().collect();
+    for line in [
+        "rollup_voucher_total 0",
+        "rollup_notice_total 1",
+        "rollup_report_total 2",
+        "rollup_gio_total 0",
+        "rollup_exception_total 0",
+        "rollup_handler_duration_seconds_bucket{handler=\"report\",le=\"+Inf\"} 2",
+        "rollup_handler_duration_seconds_count{handler=\"notice\"} 1",
+    ] {
+        assert!(lines.contains(&line), "missing `{}` in:\n{}", line, metrics);
+    }
+    std::fs::remove_file("none.output-0.bin")?;
+    std::fs::remove_file("none.report-0.bin")?;
+    std::fs::remove_file("none.report-1.bin")?;
+    Ok(())
+}
+
+#[rstest]
+#[tokio::test]
+async fn test_metrics_errors(
+    #[with(Config {
+        enable_metrics: true,
+        ..Config::new()
+    })]
+    context_future: impl Future<Output = Context>,
+) -> Result<(), Box<dyn std::error::Error>> {
+    let context = context_future.await;
+    // An odd number of hex digits fails the voucher
+    let voucher = serde_json::json!({
+        "destination": "0x1111111111111111111111111111111111111111",
+        "payload": "0x123",
+    });
+    let voucher_status = post_request(&context.address, "/voucher", voucher.to_string())
+        .await
+        .status();
+    let response = get_request(&context.address, "/metrics").await;
+    let metrics = String::from_utf8(hyper::body::to_bytes(response).await?.to_vec())?;
+    context.server_handle.stop(true).await;
+
+    assert_eq!(voucher_status, hyper::StatusCode::BAD_REQUEST);
+    let lines: Vec<&str> = metrics.lines().collect();
+    for line in [
+        "rollup_voucher_total 0",
+        "rollup_voucher_errors_total 1",
+        "rollup_notice_errors_total 0",
+        "rollup_handler_duration_seconds_count{handler=\"voucher\"} 1",
+    ] {
+        assert!(lines.contains(&line), "missing `{}` in:\n{}", line, metrics);
+    }
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_graceful_shutdown() -> Result<(), Box<dyn std::error::Error>> {
+    let rollup_fd = Arc::new(Mutex::new(create_rollup_fd()));
+    let port = rand::thread_rng().gen_range(49152..65535);
+    let config = Config {
+        http_address: HOST.to_string(),
+        http_port: port,
+        async_outputs: true,
+        ..Config::new()
+    };
+    let server_ready = Arc::new(tokio::sync::Notify::new());
+    let shutdown = Arc::new(tokio::sync::Notify::new());
+    let server = {
+        let server_ready = server_ready.clone();
+        let shutdown = shutdown.clone();
+        tokio::spawn(
+            async move { http_service::run(&config, rollup_fd, server_ready, shutdown).await },
+        )
+    };
+    server_ready.notified().await;
+    let address = format!("http://{}:{}", HOST, port);
+    let notice = serde_json::json!({ "payload": "0x00" }).to_string();
+    let response = post_request(&address, "/notice", notice).await;
+    shutdown.notify_one();
+    let result = tokio::time::timeout(std::time::Duration::from_secs(10), server).await;
+
+    assert_eq!(response.status(), hyper::StatusCode::ACCEPTED);
+    assert!(matches!(result, Ok(Ok(Ok(())))));
+    assert!(std::path::Path::new("none.output-0.bin").exists());
+    std::fs::remove_file("none.output-0.bin")?;
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_unix_socket() -> Result<(), Box<dyn std::error::Error>> {
+    let socket_path = std::env::temp_dir().join(format!(
+        "rollup-http-server-{}.sock",
+        rand::thread_rng().gen::<u32>()
+    ));
+    // Left by a server that did not shut down cleanly
+    std::fs::write(&socket_path, "stale")?;
+    let rollup_fd = Arc::new(Mutex::new(create_rollup_fd()));
+    let config = Config {
+        unix_socket: Some(socket_path.clone()),
+        ..Config::new()
+    };
+    let server_ready = Arc::new(tokio::sync::Notify::new());
+    let shutdown = Arc::new(tokio::sync::Notify::new());
+    let server = {
+        let server_ready = server_ready.clone();
+        let shutdown = shutdown.clone();
+        tokio::spawn(
+            async move { http_service::run(&config, rollup_fd, server_ready, shutdown).await },
+        )
+    };
+    server_ready.notified().await;
+    let response = {
+        let socket_path = socket_path.clone();
+        tokio::task::spawn_blocking(move || -> std::io::Result<String> {
+            use std::io::Read;
+            let mut stream = std::os::unix::net::UnixStream::connect(socket_path)?;
+            stream.write_all(
+                b"GET /healthz HTTP/1.1\r\nHost: localhost\r\nConnection: close\r\n\r\n",
+            )?;
+            let mut response = String::new();
+            stream.read_to_string(&mut response)?;
+            Ok(response)
+        })
+        .await??
+    };
+    shutdown.notify_one();
+    let result = tokio::time::timeout(std::time::Duration::from_secs(10), server).await;
+
+    // The watchdog only checks servers listening on TCP
+    let watched_config = Config {
+        unix_socket: Some(socket_path.clone()),
+        watchdog_interval_secs: Some(1),
+        ..Config::new()
+    };
+    let watched =
+        http_service::create_server(&watched_config, Arc::new(Mutex::new(RollupFd::detached())));
+
+    assert!(response.starts_with("HTTP/1.1 200 OK"), "{}", response);
+    assert!(matches!(result, Ok(Ok(Ok(())))));
+    assert!(!socket_path.exists());
+    assert_eq!(
+        watched.err().map(|e| e.kind()),
+        Some(std::io::ErrorKind::InvalidInput)
+    );
+    Ok(())
+}
+
+#[rstest]
+#[tokio::test]
+async fn test_erc20_transfer_voucher(
+    context_future: impl Future<Output = Context>,
+) -> Result<(), Box<dyn std::error::Error>> {
+    let context = context_future.await;
+    let transfer = serde_json::json!({
+        "token": "0x1111111111111111111111111111111111111111",
+        "to": "0x2222222222222222222222222222222222222222",
+        "amount": "1000",
+    });
+    let response = post_request(
+        &context.address,
+        "/voucher/erc20_transfer",
+        transfer.to_string(),
+    )
+    .await;
+    let status = response.status();
+    let invalid_token = serde_json::json!({
+        "token": "0x11",
+        "to": "0x2222222222222222222222222222222222222222",
+        "amount": "1000",
+    });
+    let invalid_response = post_request(
+        &context.address,
+        "/voucher/erc20_transfer",
+        invalid_token.to_string(),
+    )
+    .await;
+    context.server_handle.stop(true).await;
+
+    assert_eq!(status, hyper::StatusCode::CREATED);
+    assert_eq!(invalid_response.status(), hyper::StatusCode::BAD_REQUEST);
+    let data = std::fs::read("none.output-0.bin")?;
+    let decoded_voucher = ethabi::decode(
+        &[
+            ethabi::ParamType::Address,
+            ethabi::ParamType::Uint(256),
+            ethabi::ParamType::Bytes,
+        ],
+        &data[4..],
+    )?;
+    assert_eq!(
+        decoded_voucher[0].to_string(),
+        "1111111111111111111111111111111111111111"
+    );
+    assert_eq!(decoded_voucher[1], ethabi::Token::Uint(0.into()));
+    let calldata = decoded_voucher[2].clone().into_bytes().unwrap();
+    // transfer(address,uint256)
+    assert_eq!(hex::encode(&calldata[..4]), "a9059cbb");
+    assert_eq!(
+        ethabi::decode(
+            &[ethabi::ParamType::Address, ethabi::ParamType::Uint(256)],
+            &calldata[4..],
+        )?,
+        [
+            ethabi::Token::Address("0x2222222222222222222222222222222222222222".parse()?),
+            ethabi::Token::Uint(1000.into()),
+        ]
+    );
+    std::fs::remove_file("none.output-0.bin")?;
+    Ok(())
+}
+
+/// Finish with the given status and inputs, answering with the status and json body
+async fn finish_error(
+    status: &str,
+    inputs: &str,
+) -> Result<(hyper::StatusCode, serde_json::Value), Box<dyn std::error::Error>> {
+    env::set_var("CMT_INPUTS", inputs);
+    let context = create_context(Config::new()).await;
+    let response = post_request(
+        &context.address,
+        "/finish",
+        serde_json::json!({ "status": status }).to_string(),
+    )
+    .await;
+    let code = response.status();
+    let body = serde_json::from_slice(&hyper::body::to_bytes(response).await?)?;
+    context.server_handle.stop(true).await;
+    Ok((code, body))
+}
+
+#[tokio::test]
+async fn test_finish_errors() -> Result<(), Box<dyn std::error::Error>> {
+    let (code, body) = finish_error("maybe", "").await?;
+    assert_eq!(code, hyper::StatusCode::BAD_REQUEST);
+    assert_eq!(
+        body,
+        serde_json::json!({
+            "error": {
+                "code": 400,
+                "reason": "/finish: Bad Request",
+                "description": "status must be 'accept' or 'reject'",
+            }
+        })
+    );
+
+    // No input left, the device fails the finish
+    let (code, body) = finish_error("accept", "").await?;
+    assert_eq!(code, hyper::StatusCode::SERVICE_UNAVAILABLE);
+    assert_eq!(body["error"]["code"], 503);
+    assert_eq!(body["error"]["reason"], "/finish: Service Unavailable");
+
+    // An advance that is not ABI encoded cannot be decoded
+    let garbage_path = "undecodable_advance.bin";
+    std::fs::write(garbage_path, "not an advance")?;
+    let (code, body) = finish_error("accept", &format!("0:{}", garbage_path)).await?;
+    std::fs::remove_file(garbage_path)?;
+    assert_eq!(code, hyper::StatusCode::BAD_GATEWAY);
+    assert_eq!(body["error"]["code"], 502);
+    assert_eq!(body["error"]["reason"], "/finish: Bad Gateway");
+    Ok(())
+}